@@ -0,0 +1,117 @@
+//! This module detects binary files from their contents.
+
+use crate::charset::{detect_multibyte_legacy, detect_utf16};
+use encoding_rs::{Encoding, UTF_8};
+use infer::MatcherType;
+
+/// Number of bytes from the start of a file that are inspected to detect binary content.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Share of control characters and invalid UTF-8 above which a sample is considered binary.
+/// Text in a single-byte legacy encoding such as Latin-1 stays well below it, and invalid
+/// UTF-8 isn't counted for text in a multi-byte one such as Shift_JIS.
+const SUSPICIOUS_RATIO: f64 = 0.3;
+
+/// Byte order marks of UTF-16 and UTF-32 text, which is full of NUL bytes.
+/// UTF-16 without a byte order mark is recognised by `detect_utf16`.
+const WIDE_BOMS: [&[u8]; 4] = [
+    &[0x00, 0x00, 0xFE, 0xFF],
+    &[0xFF, 0xFE, 0x00, 0x00],
+    &[0xFE, 0xFF],
+    &[0xFF, 0xFE],
+];
+
+/// Checks whether the start of a file looks like binary data.
+///
+/// A sample is binary if it contains a NUL byte, if too many of its bytes are control
+/// characters or invalid UTF-8, or if it starts with the magic number of a known binary
+/// format and isn't valid UTF-8. Invalid UTF-8 is fine if the sample decodes as a
+/// multi-byte legacy encoding such as Shift_JIS or GBK.
+///
+/// # Arguments
+///
+/// * `sample` - The first bytes of the file, usually `SNIFF_LEN` of them.
+///
+/// # Returns
+///
+/// * `bool` - Whether the file should be treated as binary.
+pub fn is_binary(sample: &[u8]) -> bool {
+    is_binary_as(sample, None)
+}
+
+/// Checks whether the start of a file looks like binary data when text files are known to
+/// be in `encoding`.
+///
+/// Works like `is_binary`, except that invalid UTF-8 isn't suspicious if `encoding` is
+/// a legacy encoding.
+///
+/// # Arguments
+///
+/// * `sample` - The first bytes of the file, usually `SNIFF_LEN` of them.
+/// * `encoding` - The encoding set with `--input-encoding`, if any.
+///
+/// # Returns
+///
+/// * `bool` - Whether the file should be treated as binary.
+pub fn is_binary_as(sample: &[u8], encoding: Option<&'static Encoding>) -> bool {
+    if sample.is_empty()
+        || WIDE_BOMS.iter().any(|bom| sample.starts_with(bom))
+        || detect_utf16(sample).is_some()
+    {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let invalid_bytes = invalid_utf8_bytes(sample);
+    let legacy_text = invalid_bytes > 0
+        && (encoding.is_some_and(|encoding| encoding != UTF_8)
+            || detect_multibyte_legacy(sample).is_some());
+    let control_bytes = sample
+        .iter()
+        .filter(|&&byte| matches!(byte, 0x01..=0x08 | 0x0E..=0x1A | 0x1C..=0x1F | 0x7F))
+        .count();
+    let suspicious_bytes = control_bytes + if legacy_text { 0 } else { invalid_bytes };
+    if suspicious_bytes as f64 / sample.len() as f64 > SUSPICIOUS_RATIO {
+        return true;
+    }
+
+    // Some magic numbers are plain ASCII (e.g. `MZ`), so only trust them for non-text
+    invalid_bytes > 0 && binary_magic(sample).is_some()
+}
+
+/// Describes the type of a binary file from its magic number.
+///
+/// # Arguments
+///
+/// * `sample` - The first bytes of the file.
+///
+/// # Returns
+///
+/// * `&'static str` - The MIME type of the file, e.g. `image/png`, or `data` if unknown.
+pub fn binary_type(sample: &[u8]) -> &'static str {
+    binary_magic(sample)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("data")
+}
+
+/// Matches the sample against the magic numbers of known non-text formats.
+fn binary_magic(sample: &[u8]) -> Option<infer::Type> {
+    infer::get(sample).filter(|kind| kind.matcher_type() != MatcherType::Text)
+}
+
+/// Counts the bytes of a sample that are not part of valid UTF-8. A sequence cut off
+/// at the end of the sample is not counted.
+fn invalid_utf8_bytes(sample: &[u8]) -> usize {
+    let mut invalid = 0;
+    let mut rest = sample;
+    while let Err(err) = std::str::from_utf8(rest) {
+        let Some(len) = err.error_len() else {
+            break;
+        };
+        invalid += len;
+        rest = &rest[err.valid_up_to() + len..];
+    }
+    invalid
+}
@@ -0,0 +1,126 @@
+//! This module detects the character encoding of text files and transcodes them to UTF-8.
+
+use anyhow::{anyhow, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// Looks up an encoding by one of its WHATWG labels, e.g. `latin1`, `windows-1252` or `utf-16le`.
+///
+/// # Arguments
+///
+/// * `label` - The name of the encoding.
+///
+/// # Returns
+///
+/// * `Result<&'static Encoding>` - The encoding, or an error if the label is unknown.
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("Unknown input encoding '{}'", label))
+}
+
+/// Decodes the contents of a text file to UTF-8.
+///
+/// A byte order mark always wins. Otherwise `input_encoding` is used if given, and the
+/// encoding is detected if not: valid UTF-8 stays as is, UTF-16 without a byte order mark
+/// is recognised from its NUL bytes, and anything else is guessed from the byte frequencies
+/// of common legacy encodings.
+///
+/// # Arguments
+///
+/// * `bytes` - The contents of the file.
+/// * `input_encoding` - The encoding to use for files without a byte order mark.
+///
+/// # Returns
+///
+/// * `(String, Option<&'static str>)` - The decoded text, and the name of the encoding it was
+///   converted from, or `None` if it was UTF-8.
+pub fn decode_text(
+    bytes: &[u8],
+    input_encoding: Option<&'static Encoding>,
+) -> (String, Option<&'static str>) {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, bom_len),
+        None => (input_encoding.unwrap_or_else(|| detect_encoding(bytes)), 0),
+    };
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+    let name = (encoding != UTF_8).then(|| encoding.name());
+    (text.into_owned(), name)
+}
+
+/// Detects UTF-16 text without a byte order mark, which has a NUL byte in (nearly) every
+/// other position when it is mostly ASCII.
+///
+/// # Arguments
+///
+/// * `sample` - The first bytes of the file.
+///
+/// # Returns
+///
+/// * `Option<&'static Encoding>` - `UTF_16LE` or `UTF_16BE` if the sample looks like UTF-16.
+pub fn detect_utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    let pairs = sample.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let even_zeros = sample
+        .iter()
+        .step_by(2)
+        .take(pairs)
+        .filter(|&&b| b == 0)
+        .count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+    let mostly = |zeros: usize| zeros * 10 >= pairs * 7;
+    let hardly = |zeros: usize| zeros * 20 <= pairs;
+    if mostly(odd_zeros) && hardly(even_zeros) {
+        Some(UTF_16LE)
+    } else if mostly(even_zeros) && hardly(odd_zeros) {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Detects text in a multi-byte legacy encoding such as Shift_JIS, GBK or EUC-KR, which is
+/// mostly invalid UTF-8. The sample must decode without errors in the guessed encoding; a
+/// character cut off at the end of the sample is allowed.
+///
+/// # Arguments
+///
+/// * `sample` - The first bytes of the file.
+///
+/// # Returns
+///
+/// * `Option<&'static Encoding>` - The encoding, if the sample looks like such text.
+pub fn detect_multibyte_legacy(sample: &[u8]) -> Option<&'static Encoding> {
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, false);
+    let encoding = detector.guess(None, false);
+    if encoding.is_single_byte() || encoding == UTF_8 {
+        return None;
+    }
+
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text =
+        String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(sample.len())?);
+    let (result, _) = decoder.decode_to_string_without_replacement(sample, &mut text, false);
+    (result == DecoderResult::InputEmpty).then_some(encoding)
+}
+
+/// Detects the encoding of text without a byte order mark.
+fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    // UTF-16 first, as its NUL bytes are valid UTF-8
+    if let Some(encoding) = detect_utf16(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, false)
+}
@@ -0,0 +1,217 @@
+//! This module loads `.code2prompt.toml` configuration files and resolves named profiles.
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Name of the repository-level configuration file.
+pub const CONFIG_FILE_NAME: &str = ".code2prompt.toml";
+
+/// Key of the table holding the named profiles.
+const PROFILES_KEY: &str = "profiles";
+
+/// Settings that always hold paths.
+const PATH_SETTINGS: &[&str] = &["output", "var-file"];
+
+/// Settings that hold either a name or path(s), such as a template name or a tokenizer file.
+const NAME_OR_PATH_SETTINGS: &[&str] = &["template", "encoding"];
+
+/// Setting holding `KEY=VALUE` variables, whose value can be a `@PATH` to read it from.
+const VAR_SETTING: &str = "var";
+
+/// Finds the repository-level configuration file, looking in `start` and then in each
+/// of its parent directories.
+///
+/// # Arguments
+///
+/// * `start` - The directory (or file) to start looking from.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The path of the closest `.code2prompt.toml`, if any.
+pub fn find_repo_config(start: &Path) -> Option<PathBuf> {
+    let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Returns the path of the user-level configuration file,
+/// `$XDG_CONFIG_HOME/code2prompt/config.toml` (or the platform equivalent).
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The path, or `None` if the platform has no config directory.
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("code2prompt").join("config.toml"))
+}
+
+/// Returns the user template directory, `$XDG_CONFIG_HOME/code2prompt/templates`
+/// (or the platform equivalent).
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The path, or `None` if the platform has no config directory.
+pub fn user_template_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("code2prompt").join("templates"))
+}
+
+/// Loads and merges configuration files, then applies a named profile.
+///
+/// Files are merged in order, so values from later files override earlier ones. Profiles
+/// with the same name in several files are merged the same way. The profile's values
+/// override the top-level values. Relative paths are resolved against the directory of
+/// the file that sets them.
+///
+/// # Arguments
+///
+/// * `config_files` - The configuration files to load, lowest precedence first. Missing files are skipped.
+/// * `profile` - The name of the profile to apply, if any.
+///
+/// # Returns
+///
+/// * `Result<Table>` - The resolved settings, keyed by CLI option name.
+pub fn load_config(config_files: &[PathBuf], profile: Option<&str>) -> Result<Table> {
+    let mut merged = Table::new();
+    for path in config_files.iter().filter(|path| path.is_file()) {
+        info!("Loading config file: {}", path.display());
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let mut table: Table = contents
+            .parse()
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        resolve_paths(&mut table, path.parent().unwrap_or(Path::new("")));
+        merge_config(&mut merged, table)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+    }
+
+    let profiles = match merged.remove(PROFILES_KEY) {
+        Some(Value::Table(profiles)) => profiles,
+        _ => Table::new(),
+    };
+
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(Value::Table(settings)) => {
+                merged.extend(settings.clone());
+            }
+            _ => {
+                let mut available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                available.sort_unstable();
+                return Err(anyhow!(
+                    "Unknown profile '{}' (available: {})",
+                    name,
+                    if available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        available.join(", ")
+                    }
+                ));
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Merges one configuration table into another, key by key and profile by profile.
+fn merge_config(base: &mut Table, overrides: Table) -> Result<()> {
+    for (key, value) in overrides {
+        if key != PROFILES_KEY {
+            base.insert(key, value);
+            continue;
+        }
+
+        let Value::Table(profiles) = value else {
+            return Err(anyhow!("'{}' must be a table of profiles", PROFILES_KEY));
+        };
+        let base_profiles = base
+            .entry(PROFILES_KEY)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .expect("profiles entry is always a table");
+        for (name, settings) in profiles {
+            let Value::Table(settings) = settings else {
+                return Err(anyhow!("Profile '{}' must be a table", name));
+            };
+            match base_profiles.get_mut(&name) {
+                Some(Value::Table(existing)) => existing.extend(settings),
+                _ => {
+                    base_profiles.insert(name, Value::Table(settings));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the relative paths in a configuration table (and its profiles) against `dir`.
+///
+/// Name-or-path settings are only resolved when they name an existing file, so that
+/// template and encoding names are left untouched. A `-` output stays stdout, and only the
+/// `@PATH` values of variables are resolved.
+fn resolve_paths(table: &mut Table, dir: &Path) {
+    for (key, value) in table.iter_mut() {
+        let key = key.replace('_', "-");
+        if key == PROFILES_KEY {
+            if let Value::Table(profiles) = value {
+                for (_, settings) in profiles.iter_mut() {
+                    if let Value::Table(settings) = settings {
+                        resolve_paths(settings, dir);
+                    }
+                }
+            }
+        } else if PATH_SETTINGS.contains(&key.as_str()) {
+            map_strings(value, |path| match path {
+                "-" => path.to_string(),
+                _ => dir.join(path).to_string_lossy().into_owned(),
+            });
+        } else if NAME_OR_PATH_SETTINGS.contains(&key.as_str()) {
+            // An encoding can also be a "vocab.json,merges.txt" pair
+            map_strings(value, |names| {
+                names
+                    .split(',')
+                    .map(|name| {
+                        let path = dir.join(name.trim());
+                        if path.is_file() {
+                            path.to_string_lossy().into_owned()
+                        } else {
+                            name.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            });
+        } else if key == VAR_SETTING {
+            map_strings(value, |var| {
+                match var
+                    .split_once('=')
+                    .and_then(|(name, value)| value.strip_prefix('@').map(|path| (name, path)))
+                {
+                    Some((name, path)) => {
+                        format!("{}=@{}", name, dir.join(path).to_string_lossy())
+                    }
+                    None => var.to_string(),
+                }
+            });
+        }
+    }
+}
+
+/// Applies `f` to a string value, or to each string of an array value.
+fn map_strings(value: &mut Value, f: impl Fn(&str) -> String) {
+    match value {
+        Value::String(string) => *string = f(string),
+        Value::Array(items) => {
+            for item in items {
+                if let Value::String(string) = item {
+                    *string = f(string);
+                }
+            }
+        }
+        _ => {}
+    }
+}
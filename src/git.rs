@@ -1,11 +1,61 @@
 //! This module handles git operations.
 
 use anyhow::{Context, Result};
-use git2::{DiffOptions, Repository};
+use chrono::{DateTime, FixedOffset};
+use git2::{
+    BlameOptions, BranchType, Commit, Delta, Diff, DiffFormat, DiffOptions, FileMode, ObjectType,
+    Oid, Patch, Repository, Status, StatusOptions, Tree, TreeWalkMode, TreeWalkResult,
+};
 use log::info;
-use std::path::Path;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-/// Generates a git diff for the repository at the provided path
+/// Number of context lines around changes in a diff, as in `git diff`.
+pub const DEFAULT_DIFF_CONTEXT_LINES: u32 = 3;
+
+/// File mode of symbolic links in git trees.
+const SYMLINK_FILEMODE: i32 = 0o120000;
+
+/// Bits of an index entry's mode that hold the type of the file.
+const FILE_TYPE_MASK: u32 = 0o170000;
+
+/// Type bits of regular files in the index, executable or not.
+const REGULAR_FILEMODE: u32 = 0o100000;
+
+/// Type bits of submodules in the index.
+const GITLINK_FILEMODE: u32 = 0o160000;
+
+/// The uncommitted changes included in a working tree diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Changes staged in the index (`HEAD` to index)
+    Staged,
+    /// Changes in the working tree that aren't staged (index to working tree)
+    Unstaged,
+    /// All uncommitted changes, including untracked files (`HEAD` to working tree)
+    All,
+}
+
+impl FromStr for DiffMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "staged" => Ok(DiffMode::Staged),
+            "unstaged" => Ok(DiffMode::Unstaged),
+            "all" => Ok(DiffMode::All),
+            _ => Err(anyhow::anyhow!(
+                "Unknown diff mode {} (expected staged, unstaged or all)",
+                mode
+            )),
+        }
+    }
+}
+
+/// Generates a git diff of the staged changes for the repository at the provided path
 ///
 /// # Arguments
 ///
@@ -15,28 +65,148 @@ use std::path::Path;
 ///
 /// * `Result<String, git2::Error>` - The generated git diff as a string or an error
 pub fn get_git_diff(repo_path: &Path) -> Result<String> {
-    info!("Opening repository at path: {:?}", repo_path);
-    let repo = Repository::open(repo_path).context("Failed to open repository")?;
-    let head = repo.head().context("Failed to get repository head")?;
-    let head_tree = head.peel_to_tree().context("Failed to peel to tree")?;
+    get_working_tree_diff(repo_path, DiffMode::Staged, DEFAULT_DIFF_CONTEXT_LINES)
+}
 
-    let diff = repo
-        .diff_tree_to_index(
-            Some(&head_tree),
-            None,
-            Some(DiffOptions::new().ignore_whitespace(true)),
-        )
-        .context("Failed to generate diff")?;
+/// Generates a git diff of the uncommitted changes for the repository at the provided path
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `mode` - Which changes to include: staged, unstaged or all
+/// * `context_lines` - The number of unchanged lines to show around each change
+///
+/// # Returns
+///
+/// * `Result<String>` - The generated git diff as a string or an error
+pub fn get_working_tree_diff(
+    repo_path: &Path,
+    mode: DiffMode,
+    context_lines: u32,
+) -> Result<String> {
+    get_working_tree_diff_and_files(repo_path, mode, context_lines).map(|(diff_text, _)| diff_text)
+}
 
-    let mut diff_text = Vec::new();
-    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
-        diff_text.extend_from_slice(line.content());
-        true
-    })
-    .context("Failed to print diff")?;
+/// Lists the files changed by the uncommitted changes for the repository at the provided path,
+/// with their own part of the diff
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `mode` - Which changes to include: staged, unstaged or all
+/// * `context_lines` - The number of unchanged lines to show around each change
+///
+/// # Returns
+///
+/// * `Result<Vec<DiffFile>>` - The changed files or an error
+pub fn get_working_tree_diff_files(
+    repo_path: &Path,
+    mode: DiffMode,
+    context_lines: u32,
+) -> Result<Vec<DiffFile>> {
+    get_working_tree_diff_and_files(repo_path, mode, context_lines).map(|(_, files)| files)
+}
 
+/// Generates a git diff of the uncommitted changes and lists the changed files, with their own
+/// part of the diff, from the same diff
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `mode` - Which changes to include: staged, unstaged or all
+/// * `context_lines` - The number of unchanged lines to show around each change
+///
+/// # Returns
+///
+/// * `Result<(String, Vec<DiffFile>)>` - The generated git diff and the changed files or an error
+pub fn get_working_tree_diff_and_files(
+    repo_path: &Path,
+    mode: DiffMode,
+    context_lines: u32,
+) -> Result<(String, Vec<DiffFile>)> {
+    let mut diff_text = String::new();
+    let mut files = Vec::new();
+    visit_working_trees(repo_path, &mut |repo, prefix| {
+        let diff = working_tree_diff(repo, prefix, mode, context_lines)?;
+        diff_text.push_str(&diff_to_text(&diff)?);
+        files.extend(diff_to_files(&diff, prefix)?);
+        Ok(())
+    })?;
     info!("Generated git diff successfully");
-    Ok(String::from_utf8_lossy(&diff_text).into_owned())
+    Ok((diff_text, files))
+}
+
+/// Computes the diff of the uncommitted changes of a repository, with its paths under `prefix`
+fn working_tree_diff<'repo>(
+    repo: &'repo Repository,
+    prefix: &Path,
+    mode: DiffMode,
+    context_lines: u32,
+) -> Result<Diff<'repo>> {
+    // A repository without commits yet has no HEAD tree, everything is new
+    let head_tree = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree().context("Failed to peel to tree")?),
+        Err(_) => None,
+    };
+
+    let mut options = diff_options(prefix);
+    options.context_lines(context_lines);
+    let diff = match mode {
+        DiffMode::Staged => repo.diff_tree_to_index(head_tree.as_ref(), None, Some(&mut options)),
+        DiffMode::Unstaged => repo.diff_index_to_workdir(None, Some(&mut options)),
+        DiffMode::All => {
+            options
+                .include_untracked(true)
+                .recurse_untracked_dirs(true)
+                .show_untracked_content(true);
+            repo.diff_tree_to_workdir_with_index(head_tree.as_ref(), Some(&mut options))
+        }
+    }
+    .context("Failed to generate diff")?;
+    detect_renames(diff)
+}
+
+/// Two revisions to compare, written as `A,B`, `A..B` or `A...B`
+///
+/// Revisions can be anything git understands: local or remote branches (`origin/main`),
+/// tags, commit SHAs or expressions like `HEAD~5`. With `A...B`, `A` is replaced by the
+/// merge base of `A` and `B`, so the diff only shows the changes made on `B`, like a pull request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevisionRange {
+    /// The revision to compare from
+    pub from: String,
+    /// The revision to compare to
+    pub to: String,
+    /// Whether to compare from the merge base of the two revisions
+    pub merge_base: bool,
+}
+
+impl FromStr for RevisionRange {
+    type Err = anyhow::Error;
+
+    fn from_str(range: &str) -> Result<Self> {
+        let (from, to, merge_base) = if let Some((from, to)) = range.split_once("...") {
+            (from, to, true)
+        } else if let Some((from, to)) = range.split_once("..") {
+            (from, to, false)
+        } else if let Some((from, to)) = range.split_once(',') {
+            (from, to, false)
+        } else {
+            (range, "", false)
+        };
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() || to.is_empty() || to.contains(',') {
+            return Err(anyhow::anyhow!(
+                "Invalid revision range '{}' (expected A,B or A..B or A...B)",
+                range
+            ));
+        }
+        Ok(RevisionRange {
+            from: from.to_string(),
+            to: to.to_string(),
+            merge_base,
+        })
+    }
 }
 
 /// Generates a git diff between two branches for the repository at the provided path
@@ -55,38 +225,123 @@ pub fn get_git_diff_between_branches(
     branch1: &str,
     branch2: &str,
 ) -> Result<String> {
-    info!("Opening repository at path: {:?}", repo_path);
-    let repo = Repository::open(repo_path).context("Failed to open repository")?;
+    get_git_diff_between_revisions(
+        repo_path,
+        &RevisionRange {
+            from: branch1.to_string(),
+            to: branch2.to_string(),
+            merge_base: false,
+        },
+    )
+}
 
-    for branch in [branch1, branch2].iter() {
-        if !branch_exists(&repo, branch) {
-            return Err(anyhow::anyhow!("Branch {} doesn't exist!", branch));
-        }
-    }
+/// Generates a git diff between two revisions for the repository at the provided path
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `range` - The revisions to compare
+///
+/// # Returns
+///
+/// * `Result<String>` - The generated git diff as a string or an error
+pub fn get_git_diff_between_revisions(repo_path: &Path, range: &RevisionRange) -> Result<String> {
+    get_git_diff_and_files_between_revisions(repo_path, range).map(|(diff_text, _)| diff_text)
+}
 
-    let branch1_commit = repo.revparse_single(branch1)?.peel_to_commit()?;
-    let branch2_commit = repo.revparse_single(branch2)?.peel_to_commit()?;
+/// Lists the files changed between two revisions, with their own part of the diff
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `range` - The revisions to compare
+///
+/// # Returns
+///
+/// * `Result<Vec<DiffFile>>` - The changed files or an error
+pub fn get_diff_files_between_revisions(
+    repo_path: &Path,
+    range: &RevisionRange,
+) -> Result<Vec<DiffFile>> {
+    get_git_diff_and_files_between_revisions(repo_path, range).map(|(_, files)| files)
+}
 
-    let branch1_tree = branch1_commit.tree()?;
-    let branch2_tree = branch2_commit.tree()?;
+/// Generates a git diff between two revisions and lists the changed files, with their own
+/// part of the diff, from the same diff
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `range` - The revisions to compare
+///
+/// # Returns
+///
+/// * `Result<(String, Vec<DiffFile>)>` - The generated git diff and the changed files or an error
+pub fn get_git_diff_and_files_between_revisions(
+    repo_path: &Path,
+    range: &RevisionRange,
+) -> Result<(String, Vec<DiffFile>)> {
+    let mut diff_text = String::new();
+    let mut files = Vec::new();
+    visit_revisions(repo_path, range, &mut |repo, prefix, from, to| {
+        let diff = commits_diff(repo, prefix, from, to)?;
+        diff_text.push_str(&diff_to_text(&diff)?);
+        files.extend(diff_to_files(&diff, prefix)?);
+        Ok(())
+    })?;
+    info!("Generated git diff between revisions successfully");
+    Ok((diff_text, files))
+}
+
+/// Computes the diff between two commits of a repository, with its paths under `prefix`
+///
+/// Without a `from` commit, e.g. for a submodule added in the range, every file is new.
+fn commits_diff<'repo>(
+    repo: &'repo Repository,
+    prefix: &Path,
+    from: Option<Oid>,
+    to: Oid,
+) -> Result<Diff<'repo>> {
+    let from_tree = from.map(|from| commit_tree(repo, from)).transpose()?;
+    let to_tree = commit_tree(repo, to)?;
 
     let diff = repo
         .diff_tree_to_tree(
-            Some(&branch1_tree),
-            Some(&branch2_tree),
-            Some(DiffOptions::new().ignore_whitespace(true)),
+            from_tree.as_ref(),
+            Some(&to_tree),
+            Some(&mut diff_options(prefix)),
         )
-        .context("Failed to generate diff between branches")?;
+        .context("Failed to generate diff between revisions")?;
+    detect_renames(diff)
+}
 
-    let mut diff_text = Vec::new();
-    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
-        diff_text.extend_from_slice(line.content());
-        true
-    })
-    .context("Failed to print diff")?;
+/// Returns the tree of a commit
+fn commit_tree(repo: &Repository, commit: Oid) -> Result<Tree<'_>> {
+    let commit = repo
+        .find_commit(commit)
+        .with_context(|| format!("Failed to find commit {}", commit))?;
+    Ok(commit.tree()?)
+}
 
-    info!("Generated git diff between branches successfully");
-    Ok(String::from_utf8_lossy(&diff_text).into_owned())
+/// The options of the diffs of a repository whose working directory is at `prefix`, relative
+/// to the top-level one, so the file headers show the full paths
+fn diff_options(prefix: &Path) -> DiffOptions {
+    let mut options = DiffOptions::new();
+    options.ignore_whitespace(true);
+    if !prefix.as_os_str().is_empty() {
+        let prefix = prefix.to_string_lossy();
+        options
+            .old_prefix(format!("a/{}/", prefix))
+            .new_prefix(format!("b/{}/", prefix));
+    }
+    options
+}
+
+/// Pairs up deleted and added files with similar contents as renames, like `git diff` does
+fn detect_renames(mut diff: Diff<'_>) -> Result<Diff<'_>> {
+    diff.find_similar(None)
+        .context("Failed to detect renamed files")?;
+    Ok(diff)
 }
 
 /// Retrieves the git log between two branches for the repository at the provided path
@@ -101,53 +356,1215 @@ pub fn get_git_diff_between_branches(
 ///
 /// * `Result<String, git2::Error>` - The git log as a string or an error
 pub fn get_git_log(repo_path: &Path, branch1: &str, branch2: &str) -> Result<String> {
-    info!("Opening repository at path: {:?}", repo_path);
-    let repo = Repository::open(repo_path).context("Failed to open repository")?;
+    get_git_log_between_revisions(
+        repo_path,
+        &RevisionRange {
+            from: branch1.to_string(),
+            to: branch2.to_string(),
+            merge_base: false,
+        },
+    )
+}
 
-    for branch in [branch1, branch2].iter() {
-        if !branch_exists(&repo, branch) {
-            return Err(anyhow::anyhow!("Branch {} doesn't exist!", branch));
+/// Retrieves the git log of the commits reachable from `range.to` but not from `range.from`
+///
+/// For a log, `A...B` gives the same commits as `A..B`: the ones made on `B` since it forked from `A`.
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `range` - The revisions to compare
+///
+/// # Returns
+///
+/// * `Result<String>` - The git log as a string or an error
+pub fn get_git_log_between_revisions(repo_path: &Path, range: &RevisionRange) -> Result<String> {
+    let mut log_text = String::new();
+    visit_revisions(repo_path, range, &mut |repo, prefix, from, to| {
+        let commits = commits_between(repo, from, to)?;
+        // The commits of submodules and nested repositories come after a header with their path
+        if !prefix.as_os_str().is_empty() && !commits.is_empty() {
+            log_text.push_str(&format!("\n{}:\n", prefix.display()));
         }
+        for commit in commits {
+            log_text.push_str(&format!(
+                "{} - {}\n",
+                &commit.id().to_string()[..7],
+                commit.summary().unwrap_or("No commit message")
+            ));
+        }
+        Ok(())
+    })?;
+
+    info!("Retrieved git log successfully");
+    Ok(log_text)
+}
+
+/// A commit of a detailed git log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// The full SHA of the commit
+    pub sha: String,
+    /// The name of the author
+    pub author: String,
+    /// The email address of the author
+    pub email: String,
+    /// The author date, in RFC 3339 format with the author's UTC offset
+    pub date: String,
+    /// The first line of the commit message
+    pub summary: String,
+    /// The rest of the commit message, without the summary
+    pub body: String,
+    /// The files changed by the commit, compared to its first parent
+    pub files: Vec<FileStat>,
+}
+
+/// The number of lines a commit added and removed in one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// The path of the file, relative to the repository root
+    pub path: String,
+    /// The number of added lines
+    pub insertions: usize,
+    /// The number of removed lines
+    pub deletions: usize,
+}
+
+impl CommitInfo {
+    /// The total number of lines added by the commit
+    pub fn insertions(&self) -> usize {
+        self.files.iter().map(|file| file.insertions).sum()
     }
 
-    let branch1_commit = repo.revparse_single(branch1)?.peel_to_commit()?;
-    let branch2_commit = repo.revparse_single(branch2)?.peel_to_commit()?;
+    /// The total number of lines removed by the commit
+    pub fn deletions(&self) -> usize {
+        self.files.iter().map(|file| file.deletions).sum()
+    }
+
+    /// Converts the commit to the JSON object templates iterate over as `commits`
+    ///
+    /// # Returns
+    ///
+    /// * `Value` - The commit's fields, plus `short_sha`, `files_changed`, `insertions` and `deletions`
+    pub fn to_json(&self) -> Value {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                json!({
+                    "path": file.path,
+                    "insertions": file.insertions,
+                    "deletions": file.deletions,
+                })
+            })
+            .collect();
+        json!({
+            "sha": self.sha,
+            "short_sha": &self.sha[..7],
+            "author": self.author,
+            "email": self.email,
+            "date": self.date,
+            "summary": self.summary,
+            "body": self.body,
+            "files": files,
+            "files_changed": self.files.len(),
+            "insertions": self.insertions(),
+            "deletions": self.deletions(),
+        })
+    }
+}
+
+/// Retrieves the commits reachable from `range.to` but not from `range.from`, oldest first,
+/// with their author, date, full message and changed file stats
+///
+/// # Arguments
+///
+/// * `repo_path` - A reference to the path of the git repository
+/// * `range` - The revisions to compare
+///
+/// # Returns
+///
+/// * `Result<Vec<CommitInfo>>` - The commits or an error
+pub fn get_git_commits(repo_path: &Path, range: &RevisionRange) -> Result<Vec<CommitInfo>> {
+    let mut commits = Vec::new();
+    visit_revisions(repo_path, range, &mut |repo, prefix, from, to| {
+        for commit in commits_between(repo, from, to)? {
+            commits.push(commit_info(repo, prefix, &commit)?);
+        }
+        Ok(())
+    })?;
+
+    info!("Retrieved {} commits successfully", commits.len());
+    Ok(commits)
+}
+
+/// Collects the details of a commit, with the paths of its files under `prefix`
+fn commit_info(repo: &Repository, prefix: &Path, commit: &Commit) -> Result<CommitInfo> {
+    let author = commit.author();
+    let date = local_date(&author.when())
+        .map(|date| date.to_rfc3339())
+        .unwrap_or_default();
+
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)
+        .context("Failed to generate diff for commit")?;
+    let mut files = Vec::new();
+    for (index, delta) in diff.deltas().enumerate() {
+        let path = delta.new_file().path().or_else(|| delta.old_file().path());
+        // Binary files have no patch, and no line stats
+        let (insertions, deletions) = match Patch::from_diff(&diff, index)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats()?;
+                (insertions, deletions)
+            }
+            None => (0, 0),
+        };
+        files.push(FileStat {
+            path: path
+                .map(|path| prefix.join(path).to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            insertions,
+            deletions,
+        });
+    }
+
+    Ok(CommitInfo {
+        sha: commit.id().to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        email: author.email().unwrap_or_default().to_string(),
+        date,
+        summary: commit.summary().unwrap_or("No commit message").to_string(),
+        body: commit.body().unwrap_or_default().trim().to_string(),
+        files,
+    })
+}
+
+/// Formats commits as a detailed git log, similar to `git log --stat`
+///
+/// # Arguments
+///
+/// * `commits` - The commits to format
+///
+/// # Returns
+///
+/// * `String` - The formatted log
+pub fn format_commits(commits: &[CommitInfo]) -> String {
+    let mut log_text = String::new();
+    for commit in commits {
+        log_text.push_str(&format!(
+            "commit {}\nAuthor: {} <{}>\nDate:   {}\n\n    {}\n",
+            commit.sha, commit.author, commit.email, commit.date, commit.summary
+        ));
+        if !commit.body.is_empty() {
+            log_text.push('\n');
+            for line in commit.body.lines() {
+                if !line.is_empty() {
+                    log_text.push_str("    ");
+                }
+                log_text.push_str(line);
+                log_text.push('\n');
+            }
+        }
+        if !commit.files.is_empty() {
+            log_text.push('\n');
+            for file in &commit.files {
+                log_text.push_str(&format!(
+                    " {} | +{} -{}\n",
+                    file.path, file.insertions, file.deletions
+                ));
+            }
+            log_text.push_str(&format!(
+                " {} file{} changed, {} insertion{}(+), {} deletion{}(-)\n",
+                commit.files.len(),
+                if commit.files.len() == 1 { "" } else { "s" },
+                commit.insertions(),
+                if commit.insertions() == 1 { "" } else { "s" },
+                commit.deletions(),
+                if commit.deletions() == 1 { "" } else { "s" },
+            ));
+        }
+        log_text.push('\n');
+    }
+    log_text
+}
 
+/// Walks the commits reachable from `to` but not from `from`, oldest first
+fn commits_between(repo: &Repository, from: Option<Oid>, to: Oid) -> Result<Vec<Commit<'_>>> {
     let mut revwalk = repo.revwalk().context("Failed to create revwalk")?;
     revwalk
-        .push(branch2_commit.id())
-        .context("Failed to push branch2 commit to revwalk")?;
-    revwalk
-        .hide(branch1_commit.id())
-        .context("Failed to hide branch1 commit from revwalk")?;
+        .push(to)
+        .context("Failed to push commit to revwalk")?;
+    if let Some(from) = from {
+        revwalk
+            .hide(from)
+            .context("Failed to hide commit from revwalk")?;
+    }
     revwalk.set_sorting(git2::Sort::REVERSE)?;
 
-    let mut log_text = String::new();
+    let mut commits = Vec::new();
     for oid in revwalk {
         let oid = oid.context("Failed to get OID from revwalk")?;
-        let commit = repo.find_commit(oid).context("Failed to find commit")?;
-        log_text.push_str(&format!(
-            "{} - {}\n",
-            &commit.id().to_string()[..7],
-            commit.summary().unwrap_or("No commit message")
-        ));
+        commits.push(repo.find_commit(oid).context("Failed to find commit")?);
     }
+    Ok(commits)
+}
 
-    info!("Retrieved git log successfully");
-    Ok(log_text)
+/// Opens the repositories an operation on the provided path covers: the repository containing
+/// the path or, if there is none, the repositories nested in it
+///
+/// Each repository comes with the path of its working directory relative to the provided
+/// path, which is empty for the containing repository.
+fn open_repositories(path: &Path) -> Result<Vec<(PathBuf, Repository)>> {
+    info!("Discovering repository from path: {:?}", path);
+    let err = match Repository::discover(path) {
+        Ok(repo) => return Ok(vec![(PathBuf::new(), repo)]),
+        Err(err) => err,
+    };
+
+    let mut repos = Vec::new();
+    if path.is_dir() {
+        find_nested_repositories(path, Path::new(""), &mut repos);
+    }
+    if repos.is_empty() {
+        return Err(anyhow::Error::new(err).context("Failed to open repository"));
+    }
+    info!("Found {} nested repositories", repos.len());
+    Ok(repos)
+}
+
+/// Collects the repositories in the directories below `root.join(dir)`, without descending
+/// into the repositories themselves or into hidden directories
+fn find_nested_repositories(root: &Path, dir: &Path, repos: &mut Vec<(PathBuf, Repository)>) {
+    let Ok(read_dir) = std::fs::read_dir(root.join(dir)) else {
+        return;
+    };
+    let mut subdirs: Vec<PathBuf> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| dir.join(entry.file_name()))
+        .collect();
+    subdirs.sort();
+
+    for subdir in subdirs {
+        let path = root.join(&subdir);
+        if !path.join(".git").exists() {
+            find_nested_repositories(root, &subdir, repos);
+            continue;
+        }
+        match Repository::open(&path) {
+            Ok(repo) => repos.push((subdir, repo)),
+            Err(err) => info!("Skipping repository {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Opens the submodule checked out at a path of a repository, or returns `None` if it
+/// isn't initialized
+fn open_submodule(repo: &Repository, path: &Path) -> Option<Repository> {
+    let workdir = repo.workdir()?;
+    match Repository::open(workdir.join(path)) {
+        Ok(submodule) => Some(submodule),
+        Err(err) => {
+            info!("Skipping submodule {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Calls `visit` with each repository covered by the provided path and its submodules,
+/// recursively, and the path of its working directory (see `open_repositories`)
+fn visit_working_trees(
+    repo_path: &Path,
+    visit: &mut dyn FnMut(&Repository, &Path) -> Result<()>,
+) -> Result<()> {
+    fn visit_repo(
+        repo: &Repository,
+        prefix: &Path,
+        visit: &mut dyn FnMut(&Repository, &Path) -> Result<()>,
+    ) -> Result<()> {
+        visit(repo, prefix)?;
+        for submodule in repo.submodules().context("Failed to list submodules")? {
+            if let Some(sub_repo) = open_submodule(repo, submodule.path()) {
+                visit_repo(&sub_repo, &prefix.join(submodule.path()), visit)?;
+            }
+        }
+        Ok(())
+    }
+
+    for (prefix, repo) in open_repositories(repo_path)? {
+        visit_repo(&repo, &prefix, visit)?;
+    }
+    Ok(())
+}
+
+/// A callback taking a repository, the path of its working directory and two of its commits
+type RangeVisitor<'a> = dyn FnMut(&Repository, &Path, Option<Oid>, Oid) -> Result<()> + 'a;
+
+/// Calls `visit` with each repository covered by the provided path, the path of its working
+/// directory and the two commits a revision range resolves to in it
+///
+/// Submodules whose recorded commit changed over the range are visited as well, recursively,
+/// with the old and new recorded commits. The old one is `None` if the submodule was added.
+fn visit_revisions(
+    repo_path: &Path,
+    range: &RevisionRange,
+    visit: &mut RangeVisitor,
+) -> Result<()> {
+    fn visit_repo(
+        repo: &Repository,
+        prefix: &Path,
+        from: Option<Oid>,
+        to: Oid,
+        visit: &mut RangeVisitor,
+    ) -> Result<()> {
+        visit(repo, prefix, from, to)?;
+
+        let from_tree = from.map(|from| commit_tree(repo, from)).transpose()?;
+        let to_tree = commit_tree(repo, to)?;
+        let diff = repo
+            .diff_tree_to_tree(from_tree.as_ref(), Some(&to_tree), None)
+            .context("Failed to generate diff between revisions")?;
+        for delta in diff.deltas() {
+            // Removed submodules have no commits left to show
+            let (old_file, new_file) = (delta.old_file(), delta.new_file());
+            if new_file.mode() != FileMode::Commit {
+                continue;
+            }
+            let Some(path) = new_file.path() else {
+                continue;
+            };
+            let Some(sub_repo) = open_submodule(repo, path) else {
+                continue;
+            };
+            // The checkout may not have fetched the recorded commits
+            let old = (old_file.mode() == FileMode::Commit).then(|| old_file.id());
+            let fetched = |id: Oid| sub_repo.find_commit(id).is_ok();
+            if !fetched(new_file.id()) || old.is_some_and(|old| !fetched(old)) {
+                info!("Skipping submodule {}: commits not fetched", path.display());
+                continue;
+            }
+            visit_repo(&sub_repo, &prefix.join(path), old, new_file.id(), visit)
+                .with_context(|| format!("Failed to read submodule {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    for (prefix, repo) in open_repositories(repo_path)? {
+        let (from, to) = resolve_range(&repo, range).with_context(|| {
+            if prefix.as_os_str().is_empty() {
+                String::from("Failed to resolve revision range")
+            } else {
+                format!("Failed to resolve revision range in {}", prefix.display())
+            }
+        })?;
+        visit_repo(&repo, &prefix, Some(from.id()), to.id(), visit)?;
+    }
+    Ok(())
+}
+
+/// Resolves both ends of a revision range to commits, replacing the start with the
+/// merge base for `A...B`
+fn resolve_range<'repo>(
+    repo: &'repo Repository,
+    range: &RevisionRange,
+) -> Result<(Commit<'repo>, Commit<'repo>)> {
+    let from_commit = resolve_commit(repo, &range.from)?;
+    let to_commit = resolve_commit(repo, &range.to)?;
+    if !range.merge_base {
+        return Ok((from_commit, to_commit));
+    }
+
+    let base = repo
+        .merge_base(from_commit.id(), to_commit.id())
+        .with_context(|| {
+            format!(
+                "Revisions {} and {} have no common ancestor",
+                range.from, range.to
+            )
+        })?;
+    Ok((repo.find_commit(base)?, to_commit))
+}
+
+/// Resolves a revision to a commit
+///
+/// Anything `git rev-parse` accepts works. A name that is neither a local branch, a tag nor a
+/// commit is also looked up among the remote-tracking branches, so `main` finds `origin/main`
+/// in a clone where `main` was never checked out.
+///
+/// # Arguments
+///
+/// * `repo` - A reference to the `Repository` to look the revision up in
+/// * `rev` - The revision, e.g. "main", "origin/main", "v1.2.0", a SHA or "HEAD~5"
+///
+/// # Returns
+///
+/// * `Result<Commit>` - The commit the revision points to
+pub fn resolve_commit<'repo>(repo: &'repo Repository, rev: &str) -> Result<Commit<'repo>> {
+    let object = match repo.revparse_single(rev) {
+        Ok(object) => object,
+        Err(err) => {
+            let remote_branch = repo
+                .branches(Some(BranchType::Remote))?
+                .filter_map(|branch| branch.ok())
+                .map(|(branch, _)| branch)
+                .find(|branch| {
+                    branch
+                        .name()
+                        .ok()
+                        .flatten()
+                        .and_then(|name| name.split_once('/'))
+                        .is_some_and(|(_, name)| name == rev)
+                });
+            match remote_branch {
+                Some(branch) => branch.into_reference().peel(ObjectType::Commit)?,
+                None => {
+                    return Err(anyhow::Error::new(err)
+                        .context(format!("Failed to resolve revision {}", rev)))
+                }
+            }
+        }
+    };
+    object
+        .peel_to_commit()
+        .with_context(|| format!("Revision {} doesn't point to a commit", rev))
+}
+
+/// Who last changed a line of a file, and when
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineBlame {
+    /// The abbreviated SHA of the commit, or `0000000` if the line isn't committed yet
+    pub short_sha: String,
+    /// The name of the author of the commit
+    pub author: String,
+    /// The author date of the commit, as `YYYY-MM-DD`, or empty if the line isn't committed yet
+    pub date: String,
+}
+
+impl LineBlame {
+    /// The blame of a line that was added or changed in the working tree
+    fn uncommitted() -> Self {
+        LineBlame {
+            short_sha: "0000000".to_string(),
+            author: "Not Committed Yet".to_string(),
+            date: String::new(),
+        }
+    }
+}
+
+/// Looks up who last changed each line of the files of a repository, with `git blame`
+pub struct Blamer {
+    repo: Repository,
+    workdir: PathBuf,
+    /// The commit to blame the files as of, instead of the working tree
+    rev: Option<Oid>,
+}
+
+impl Blamer {
+    /// Opens the repository containing the provided path
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path inside the git repository
+    /// * `rev` - The revision the files are read from, or `None` for the working tree
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Blamer>` - The blamer or an error if the path isn't in a repository
+    pub fn discover(path: &Path, rev: Option<&str>) -> Result<Self> {
+        let (repo, workdir) = discover_workdir(path)?;
+        let rev = rev
+            .map(|rev| resolve_commit(&repo, rev).map(|commit| commit.id()))
+            .transpose()?;
+        Ok(Blamer { repo, workdir, rev })
+    }
+
+    /// Blames each line of a file, as it currently is in the working tree, or as it is in
+    /// the revision the blamer was opened with
+    ///
+    /// Lines that were changed since the last commit, and all the lines of untracked
+    /// files, are blamed as not committed yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `contents` - The current contents of the file
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<LineBlame>>` - The blame of each line of the contents, or an error if the
+    ///   file isn't in the repository
+    pub fn blame(&self, file_path: &Path, contents: &[u8]) -> Result<Vec<LineBlame>> {
+        let relative = &relative_to_workdir(&self.workdir, file_path)?;
+        let line_count =
+            contents.split(|&byte| byte == b'\n').count() - usize::from(contents.ends_with(b"\n"));
+
+        let newest_tree = match self.rev {
+            Some(rev) => self.repo.find_commit(rev).and_then(|commit| commit.tree()),
+            None => self.repo.head().and_then(|head| head.peel_to_tree()),
+        };
+        let committed = newest_tree.is_ok_and(|tree| tree.get_path(relative).is_ok());
+        if !committed {
+            return Ok(vec![LineBlame::uncommitted(); line_count]);
+        }
+
+        let mut blame_options = BlameOptions::new();
+        if let Some(rev) = self.rev {
+            blame_options.newest_commit(rev);
+        }
+        let file_blame = self
+            .repo
+            .blame_file(relative, Some(&mut blame_options))
+            .with_context(|| format!("Failed to blame {}", relative.display()))?;
+        // The contents of a revision are exactly what was blamed, the working tree may differ
+        let buffer_blame;
+        let blame = if self.rev.is_some() {
+            &file_blame
+        } else {
+            buffer_blame = file_blame
+                .blame_buffer(contents)
+                .with_context(|| format!("Failed to blame {}", relative.display()))?;
+            &buffer_blame
+        };
+        let lines = (1..=line_count)
+            .map(|line| match blame.get_line(line) {
+                Some(hunk) if !hunk.final_commit_id().is_zero() => {
+                    let signature = hunk.final_signature();
+                    LineBlame {
+                        short_sha: hunk.final_commit_id().to_string()[..7].to_string(),
+                        author: signature.name().unwrap_or_default().to_string(),
+                        date: local_date(&signature.when())
+                            .map(|date| date.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default(),
+                    }
+                }
+                _ => LineBlame::uncommitted(),
+            })
+            .collect();
+        Ok(lines)
+    }
+}
+
+/// A commit that touched a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// The full SHA of the commit
+    pub sha: String,
+    /// The author date, in RFC 3339 format with the author's UTC offset
+    pub date: String,
+    /// The first line of the commit message
+    pub summary: String,
+}
+
+impl HistoryEntry {
+    /// Converts the entry to the JSON object of a file's `history`
+    ///
+    /// # Returns
+    ///
+    /// * `Value` - The entry's fields, plus `short_sha`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sha": self.sha,
+            "short_sha": &self.sha[..7],
+            "date": self.date,
+            "summary": self.summary,
+        })
+    }
+}
+
+/// Looks up the last commits that touched each file of a repository
+///
+/// The history of `HEAD` is walked lazily, newest first, and only as far as needed: each commit
+/// is compared to its first parent, and the files it touched are remembered for later lookups.
+/// The walk stops early for a file once the commit that added it is found, and doesn't start at
+/// all for files that aren't in the starting commit (e.g. untracked ones).
+pub struct FileHistory {
+    repo: Repository,
+    workdir: PathBuf,
+    /// The tree of the commit the history starts from
+    tree: Option<Oid>,
+    /// The maximum number of commits to keep per file
+    limit: usize,
+    walk: RefCell<HistoryWalk>,
+}
+
+/// How far `FileHistory` has walked, and what it found on the way
+#[derive(Default)]
+struct HistoryWalk {
+    /// The commits still to visit, by commit time
+    pending: BinaryHeap<(i64, Oid)>,
+    queued: HashSet<Oid>,
+    touched: HashMap<PathBuf, Vec<HistoryEntry>>,
+    /// Files whose adding commit has been found, so no older commit can touch them
+    added: HashSet<PathBuf>,
+}
+
+impl HistoryWalk {
+    /// Queues a commit to visit, unless it was queued before
+    fn queue(&mut self, commit: &Commit) {
+        if self.queued.insert(commit.id()) {
+            self.pending.push((commit.time().seconds(), commit.id()));
+        }
+    }
+}
+
+impl FileHistory {
+    /// Opens the repository containing the provided path
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path inside the git repository
+    /// * `rev` - The revision to start the history from, or `None` for `HEAD`
+    /// * `limit` - The maximum number of commits to return per file
+    ///
+    /// # Returns
+    ///
+    /// * `Result<FileHistory>` - The file history or an error if the path isn't in a repository
+    pub fn discover(path: &Path, rev: Option<&str>, limit: usize) -> Result<Self> {
+        let (repo, workdir) = discover_workdir(path)?;
+        let mut walk = HistoryWalk::default();
+        let tree = {
+            let start = match rev {
+                Some(rev) => Some(resolve_commit(&repo, rev)?),
+                // A repository without commits yet has no history at all
+                None => repo.head().ok().and_then(|head| head.peel_to_commit().ok()),
+            };
+            start.map(|commit| {
+                walk.queue(&commit);
+                commit.tree_id()
+            })
+        };
+        Ok(FileHistory {
+            repo,
+            workdir,
+            tree,
+            limit,
+            walk: RefCell::new(walk),
+        })
+    }
+
+    /// Retrieves the last commits that touched a file, newest first
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<HistoryEntry>>` - Up to `limit` commits, or an error if the file isn't in
+    ///   the repository
+    pub fn for_file(&self, file_path: &Path) -> Result<Vec<HistoryEntry>> {
+        let relative = relative_to_workdir(&self.workdir, file_path)?;
+        let Some(tree) = self.tree else {
+            return Ok(Vec::new());
+        };
+        let tree = self.repo.find_tree(tree).context("Failed to find tree")?;
+        if tree.get_path(&relative).is_err() {
+            return Ok(Vec::new());
+        }
+
+        let mut walk = self.walk.borrow_mut();
+        while walk.touched.get(&relative).map_or(0, Vec::len) < self.limit
+            && !walk.added.contains(&relative)
+        {
+            let Some((_, oid)) = walk.pending.pop() else {
+                break;
+            };
+            let commit = self
+                .repo
+                .find_commit(oid)
+                .context("Failed to find commit")?;
+            for parent in commit.parents() {
+                walk.queue(&parent);
+            }
+
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let diff = self
+                .repo
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)
+                .context("Failed to generate diff for commit")?;
+            let entry = HistoryEntry {
+                sha: commit.id().to_string(),
+                date: local_date(&commit.author().when())
+                    .map(|date| date.to_rfc3339())
+                    .unwrap_or_default(),
+                summary: commit.summary().unwrap_or("No commit message").to_string(),
+            };
+            // A merge adds every file its other parents added, so only trust other commits
+            let linear = commit.parent_count() <= 1;
+            for delta in diff.deltas() {
+                let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+                    continue;
+                };
+                if walk.added.contains(path) {
+                    continue;
+                }
+                let entries = walk.touched.entry(path.to_path_buf()).or_default();
+                if entries.len() < self.limit {
+                    entries.push(entry.clone());
+                }
+                if linear && delta.status() == Delta::Added {
+                    walk.added.insert(path.to_path_buf());
+                }
+            }
+        }
+        Ok(walk.touched.get(&relative).cloned().unwrap_or_default())
+    }
 }
 
-/// Checks if a local branch exists in the given repository
+/// A file or directory of a `Snapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    /// The path of the entry, relative to the directory that was walked
+    pub path: PathBuf,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// The blob holding the contents of a file
+    pub blob: Option<Oid>,
+}
+
+/// The files of a repository as of a given revision, read from the git object database
+/// rather than from the working tree, so nothing needs to be checked out
+pub struct Snapshot {
+    repo: Repository,
+    workdir: PathBuf,
+    tree: Oid,
+}
+
+impl Snapshot {
+    /// Opens the repository containing the provided path, as of a revision
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path inside the git repository
+    /// * `rev` - The revision to read, e.g. "v1.4.0", "origin/main" or a SHA
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Snapshot>` - The snapshot or an error if the revision can't be resolved
+    pub fn open(path: &Path, rev: &str) -> Result<Self> {
+        let (repo, workdir) = discover_workdir(path)?;
+        let tree = resolve_commit(&repo, rev)?.tree_id();
+        Ok(Snapshot {
+            repo,
+            workdir,
+            tree,
+        })
+    }
+
+    /// Looks up a path in the snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path, as it would be in the working tree
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<SnapshotEntry>>` - The entry, with its path relative to the repository
+    ///   root, or `None` if the path doesn't exist in the revision
+    pub fn entry(&self, path: &Path) -> Result<Option<SnapshotEntry>> {
+        let relative = relative_to_workdir(&self.workdir, path)?;
+        if relative.as_os_str().is_empty() {
+            return Ok(Some(SnapshotEntry {
+                path: relative,
+                is_dir: true,
+                blob: None,
+            }));
+        }
+        let tree = self.repo.find_tree(self.tree)?;
+        let Ok(entry) = tree.get_path(&relative) else {
+            return Ok(None);
+        };
+        Ok(match entry.kind() {
+            Some(ObjectType::Tree) => Some(SnapshotEntry {
+                path: relative,
+                is_dir: true,
+                blob: None,
+            }),
+            Some(ObjectType::Blob) if entry.filemode() != SYMLINK_FILEMODE => Some(SnapshotEntry {
+                path: relative,
+                is_dir: false,
+                blob: Some(entry.id()),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Lists everything below a directory of the snapshot, each directory before its contents
+    ///
+    /// Symbolic links and submodules are left out, as they have no contents of their own.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory, as it would be in the working tree
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<SnapshotEntry>>` - The entries, with paths relative to `dir`
+    pub fn walk(&self, dir: &Path) -> Result<Vec<SnapshotEntry>> {
+        let relative = relative_to_workdir(&self.workdir, dir)?;
+        let mut tree = self.repo.find_tree(self.tree)?;
+        if !relative.as_os_str().is_empty() {
+            let id = tree
+                .get_path(&relative)
+                .with_context(|| format!("{} doesn't exist in the revision", relative.display()))?
+                .id();
+            tree = self.repo.find_tree(id)?;
+        }
+
+        let mut entries = Vec::new();
+        tree.walk(TreeWalkMode::PreOrder, |parent, entry| {
+            let Some(name) = entry.name() else {
+                return TreeWalkResult::Skip;
+            };
+            let path = Path::new(parent).join(name);
+            match entry.kind() {
+                Some(ObjectType::Tree) => entries.push(SnapshotEntry {
+                    path,
+                    is_dir: true,
+                    blob: None,
+                }),
+                Some(ObjectType::Blob) if entry.filemode() != SYMLINK_FILEMODE => {
+                    entries.push(SnapshotEntry {
+                        path,
+                        is_dir: false,
+                        blob: Some(entry.id()),
+                    })
+                }
+                _ => {}
+            }
+            TreeWalkResult::Ok
+        })
+        .context("Failed to walk the tree of the revision")?;
+        Ok(entries)
+    }
+
+    /// Reads the contents of a file of the snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * `blob` - The blob of the file, from a `SnapshotEntry`
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>>` - The contents of the file
+    pub fn read(&self, blob: Oid) -> Result<Vec<u8>> {
+        Ok(self
+            .repo
+            .find_blob(blob)
+            .context("Failed to read file from the revision")?
+            .content()
+            .to_vec())
+    }
+}
+
+/// Opens the repository containing a path, with the canonical path of its working directory
+fn discover_workdir(path: &Path) -> Result<(Repository, PathBuf)> {
+    info!("Discovering repository from path: {:?}", path);
+    let repo = Repository::discover(path).context("Failed to open repository")?;
+    let workdir = canonical_workdir(&repo)?;
+    Ok((repo, workdir))
+}
+
+/// Returns the canonical path of the working directory of a repository
+fn canonical_workdir(repo: &Repository) -> Result<PathBuf> {
+    let workdir = repo
+        .workdir()
+        .context("Repository has no working directory")?;
+    Ok(workdir
+        .canonicalize()
+        .unwrap_or_else(|_| workdir.to_path_buf()))
+}
+
+/// Returns the path of a file relative to the working directory of its repository
+fn relative_to_workdir(workdir: &Path, file_path: &Path) -> Result<PathBuf> {
+    let file_path = file_path
+        .canonicalize()
+        .unwrap_or_else(|_| file_path.to_path_buf());
+    file_path
+        .strip_prefix(workdir)
+        .map(Path::to_path_buf)
+        .with_context(|| format!("{} is not in the repository", file_path.display()))
+}
+
+/// Converts a git timestamp to a date in its own UTC offset
+fn local_date(time: &git2::Time) -> Option<DateTime<FixedOffset>> {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)?;
+    DateTime::from_timestamp(time.seconds(), 0).map(|date| date.with_timezone(&offset))
+}
+
+/// Lists the files changed in the repository containing the provided path
+///
+/// Without a revision, these are the files modified in the working tree or the index,
+/// including untracked files. With a revision, they are the files that differ between
+/// that revision and `HEAD`. Deleted files are left out. Files changed in submodules are
+/// included, see `visit_working_trees` and `visit_revisions`.
 ///
 /// # Arguments
 ///
-/// * `repo` - A reference to the `Repository` where the branch should be checked
-/// * `branch_name` - A string slice that holds the name of the branch to check
+/// * `repo_path` - A path inside the git repository
+/// * `since` - The revision to compare `HEAD` against (e.g., "main" or "HEAD~3"), if any
 ///
 /// # Returns
 ///
-/// * `bool` - `true` if the branch exists, `false` otherwise
-fn branch_exists(repo: &Repository, branch_name: &str) -> bool {
-    repo.find_branch(branch_name, git2::BranchType::Local)
-        .is_ok()
+/// * `Result<HashSet<PathBuf>>` - The absolute paths of the changed files
+pub fn get_changed_files(repo_path: &Path, since: Option<&str>) -> Result<HashSet<PathBuf>> {
+    let mut changed = HashSet::new();
+    match since {
+        Some(rev) => {
+            let range = RevisionRange {
+                from: rev.to_string(),
+                to: "HEAD".to_string(),
+                merge_base: false,
+            };
+            visit_revisions(repo_path, &range, &mut |repo, _prefix, from, to| {
+                let workdir = canonical_workdir(repo)?;
+                let diff = commits_diff(repo, Path::new(""), from, to)?;
+                for delta in diff.deltas() {
+                    if delta.status() != Delta::Deleted {
+                        if let Some(path) = delta.new_file().path() {
+                            changed.insert(workdir.join(path));
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        None => {
+            visit_working_trees(repo_path, &mut |repo, _prefix| {
+                let workdir = canonical_workdir(repo)?;
+                let statuses = repo
+                    .statuses(Some(
+                        StatusOptions::new()
+                            .include_untracked(true)
+                            .recurse_untracked_dirs(true),
+                    ))
+                    .context("Failed to get repository status")?;
+                let deleted = Status::WT_DELETED | Status::INDEX_DELETED;
+                for entry in statuses.iter() {
+                    if entry.status().intersects(deleted) || entry.status().is_ignored() {
+                        continue;
+                    }
+                    if let Some(path) = entry.path() {
+                        changed.insert(workdir.join(path));
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    info!("Found {} changed files", changed.len());
+    Ok(changed)
+}
+
+/// Lists the files tracked by the repository containing the provided path
+///
+/// These are the regular files in the index, i.e. what a fresh clone would contain plus
+/// any staged changes, and the files tracked by its initialized submodules. Symlinks and
+/// tracked files deleted from the working tree are left out. Outside of a repository, the
+/// files of the repositories nested in the path are listed instead.
+///
+/// # Arguments
+///
+/// * `repo_path` - A path inside the git repository
+/// * `include_untracked` - Whether to add the untracked files that aren't ignored
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>>` - The absolute paths of the files, sorted
+pub fn get_tracked_files(repo_path: &Path, include_untracked: bool) -> Result<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+    for (_, repo) in open_repositories(repo_path)? {
+        collect_tracked_files(&repo, include_untracked, &mut files)?;
+    }
+    info!("Found {} tracked files", files.len());
+    Ok(files.into_iter().collect())
+}
+
+/// Adds the files tracked by a repository and its submodules to `files`, see `get_tracked_files`
+fn collect_tracked_files(
+    repo: &Repository,
+    include_untracked: bool,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<()> {
+    let workdir = canonical_workdir(repo)?;
+    let index = repo.index().context("Failed to read the index")?;
+
+    // Conflicted files have several entries, one per stage
+    for entry in index.iter() {
+        let path = PathBuf::from(String::from_utf8_lossy(&entry.path).as_ref());
+        match entry.mode & FILE_TYPE_MASK {
+            REGULAR_FILEMODE if workdir.join(&path).is_file() => {
+                files.insert(workdir.join(&path));
+            }
+            GITLINK_FILEMODE => {
+                if let Some(submodule) = open_submodule(repo, &path) {
+                    collect_tracked_files(&submodule, include_untracked, files)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if include_untracked {
+        let statuses = repo
+            .statuses(Some(
+                StatusOptions::new()
+                    .include_untracked(true)
+                    .recurse_untracked_dirs(true)
+                    .include_ignored(false),
+            ))
+            .context("Failed to get repository status")?;
+        for entry in statuses.iter() {
+            if entry.status().contains(Status::WT_NEW) {
+                if let Some(path) = entry.path() {
+                    files.insert(workdir.join(path));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How a file was changed by a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    /// The file is new, or untracked
+    Added,
+    /// The file's contents or mode changed
+    Modified,
+    /// The file was removed
+    Deleted,
+    /// The file was moved, and possibly changed
+    Renamed,
+}
+
+impl ChangeType {
+    /// The name of the change type as exposed to templates
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeType::Added => "added",
+            ChangeType::Modified => "modified",
+            ChangeType::Deleted => "deleted",
+            ChangeType::Renamed => "renamed",
+        }
+    }
+}
+
+/// A file changed by a diff, with its own part of the diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFile {
+    /// The path of the file, relative to the repository root (the old path if it was deleted)
+    pub path: String,
+    /// The path the file was renamed from, if it was renamed
+    pub old_path: Option<String>,
+    /// How the file was changed
+    pub change_type: ChangeType,
+    /// The number of added lines
+    pub additions: usize,
+    /// The number of removed lines
+    pub deletions: usize,
+    /// Whether the file is binary, in which case it has no hunks
+    pub binary: bool,
+    /// The hunks of the file's diff, each with its `@@` header
+    pub hunks: String,
+}
+
+impl DiffFile {
+    /// Converts the file to the JSON object templates iterate over as `diff_files`
+    ///
+    /// # Returns
+    ///
+    /// * `Value` - The file's fields, with the change type as a string
+    pub fn to_json(&self) -> Value {
+        json!({
+            "path": self.path,
+            "old_path": self.old_path,
+            "change_type": self.change_type.as_str(),
+            "additions": self.additions,
+            "deletions": self.deletions,
+            "binary": self.binary,
+            "hunks": self.hunks,
+        })
+    }
+}
+
+/// Splits a diff into its files, with their stats and hunks
+fn diff_to_files(diff: &Diff, prefix: &Path) -> Result<Vec<DiffFile>> {
+    let mut files = Vec::new();
+    for (index, delta) in diff.deltas().enumerate() {
+        let change_type = match delta.status() {
+            Delta::Added | Delta::Untracked | Delta::Copied => ChangeType::Added,
+            Delta::Deleted => ChangeType::Deleted,
+            Delta::Renamed => ChangeType::Renamed,
+            _ => ChangeType::Modified,
+        };
+        let path_of = |file: git2::DiffFile| {
+            file.path()
+                .map(|path| prefix.join(path).to_string_lossy().into_owned())
+        };
+        let old_path = path_of(delta.old_file());
+        let path = path_of(delta.new_file())
+            .or_else(|| old_path.clone())
+            .unwrap_or_default();
+
+        let mut file = DiffFile {
+            path,
+            old_path: (change_type == ChangeType::Renamed)
+                .then_some(old_path)
+                .flatten(),
+            change_type,
+            additions: 0,
+            deletions: 0,
+            binary: delta.flags().is_binary(),
+            hunks: String::new(),
+        };
+        // Binary and unchanged files have no patch
+        if let Some(mut patch) = Patch::from_diff(diff, index)? {
+            let (_, additions, deletions) = patch.line_stats()?;
+            file.additions = additions;
+            file.deletions = deletions;
+            file.binary |= patch.delta().flags().is_binary();
+
+            let mut hunks = Vec::new();
+            patch
+                .print(&mut |_delta, _hunk, line| {
+                    // Skip the file header, which is the same for every hunk
+                    if line.origin() == 'F' {
+                        return true;
+                    }
+                    if matches!(line.origin(), '+' | '-' | ' ') {
+                        hunks.push(line.origin() as u8);
+                    }
+                    hunks.extend_from_slice(line.content());
+                    true
+                })
+                .context("Failed to print diff")?;
+            file.hunks = String::from_utf8_lossy(&hunks).into_owned();
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
+
+/// Prints a diff in patch format, with the `+`, `-` and ` ` markers of changed and context lines
+fn diff_to_text(diff: &Diff) -> Result<String> {
+    let mut diff_text = Vec::new();
+    diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            diff_text.push(line.origin() as u8);
+        }
+        diff_text.extend_from_slice(line.content());
+        true
+    })
+    .context("Failed to print diff")?;
+    Ok(String::from_utf8_lossy(&diff_text).into_owned())
 }
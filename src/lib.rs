@@ -1,13 +1,31 @@
+pub mod binary;
+pub mod charset;
+pub mod config;
 pub mod filter;
 pub mod git;
 pub mod path;
 pub mod template;
 pub mod token;
 
-pub use git::{get_git_diff, get_git_diff_between_branches, get_git_log};
-pub use path::{label, shorten_long_base64_strings, traverse_directory, wrap_code_block};
+pub use config::{find_repo_config, load_config, user_config_path, user_template_dir};
+pub use git::{
+    format_commits, get_changed_files, get_diff_files_between_revisions, get_git_commits,
+    get_git_diff, get_git_diff_and_files_between_revisions, get_git_diff_between_branches,
+    get_git_diff_between_revisions, get_git_log, get_git_log_between_revisions, get_tracked_files,
+    get_working_tree_diff, get_working_tree_diff_and_files, get_working_tree_diff_files,
+    ChangeType, CommitInfo, DiffFile, DiffMode, FileStat, RevisionRange,
+    DEFAULT_DIFF_CONTEXT_LINES,
+};
+pub use path::{
+    format_size, label, shorten_long_base64_strings, traverse_directory, wrap_code_block,
+    LimitedFile, TraverseOptions,
+};
 pub use template::{
-    copy_file_to_clipboard, copy_to_clipboard, handle_undefined_variables, handlebars_setup,
-    read_paths_from_clipboard, render_template, write_to_file,
+    copy_file_to_clipboard, copy_to_clipboard, find_template, handle_undefined_variables,
+    handlebars_setup, list_templates, load_template_variables, read_paths_from_clipboard,
+    render_template, write_to_file, Template, TemplateSource, DEFAULT_TEMPLATE_NAME,
+};
+pub use token::{
+    count_tokens, fit_to_token_budget, get_model, get_model_info, get_tokenizer, split_into_parts,
+    BudgetCut, ModelInfo, Tokenizer,
 };
-pub use token::{count_tokens, get_model_info, get_tokenizer};
@@ -5,21 +5,45 @@
 
 use anyhow::{Context, Result};
 use chrono::Local;
-use clap::Parser;
+use clap::builder::{PossibleValuesParser, Resettable, TypedValueParser};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser};
 use code2prompt::{
-    copy_file_to_clipboard, copy_to_clipboard, get_git_diff, get_git_diff_between_branches,
-    get_git_log, get_model_info, get_tokenizer, handle_undefined_variables, handlebars_setup,
-    label, read_paths_from_clipboard, render_template, traverse_directory, write_to_file,
+    copy_file_to_clipboard, copy_to_clipboard, find_repo_config, find_template,
+    fit_to_token_budget, format_commits, format_size, get_changed_files, get_git_commits,
+    get_git_diff_and_files_between_revisions, get_git_log_between_revisions, get_model,
+    get_model_info, get_tokenizer, get_working_tree_diff, get_working_tree_diff_and_files,
+    handle_undefined_variables, handlebars_setup, label, list_templates, load_config,
+    load_template_variables, read_paths_from_clipboard, render_template, split_into_parts,
+    traverse_directory, user_config_path, user_template_dir, write_to_file, BudgetCut, DiffFile,
+    DiffMode, LimitedFile, ModelInfo, RevisionRange, TemplateSource, TraverseOptions,
+    DEFAULT_DIFF_CONTEXT_LINES, DEFAULT_TEMPLATE_NAME,
 };
 use colored::*;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error};
+use log::debug;
 use serde_json::json;
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 
-const DEFAULT_TEMPLATE_NAME: &str = "default";
-const CUSTOM_TEMPLATE_NAME: &str = "custom";
+/// CLI options that only make sense on the command line and can't be set in a config file.
+const CLI_ONLY_OPTIONS: &[&str] = &[
+    "paths",
+    "profile",
+    "read",
+    "list_templates",
+    "help",
+    "version",
+];
+
+/// The diff modes of `--diff` and the template variables holding their diffs, next to the
+/// `git_diff` of the selected mode.
+const DIFF_MODES: [(DiffMode, &str); 3] = [
+    (DiffMode::Staged, "git_diff_staged"),
+    (DiffMode::Unstaged, "git_diff_unstaged"),
+    (DiffMode::All, "git_diff_all"),
+];
 
 /// CLI Arguments – accepts one or more paths.
 #[derive(Parser)]
@@ -27,7 +51,7 @@ const CUSTOM_TEMPLATE_NAME: &str = "custom";
 #[command(arg_required_else_help = true)]
 struct Cli {
     /// Paths to one or more codebase directories
-    #[arg(required_unless_present = "read")]
+    #[arg(required_unless_present_any = ["read", "list_templates"])]
     paths: Vec<PathBuf>,
 
     /// File extensions to include (comma-separated)
@@ -63,41 +87,81 @@ struct Cli {
     #[clap(
         short = 'c',
         long,
-        help = "Optional tokenizer to use for token count (default is \"cl100k\")\nValid choices: cl100k, p50k, p50k_edit, r50k, gpt2"
+        help = "Optional tokenizer to use for token count (default is \"cl100k\", or\nthe encoding of --model)\nValid choices: o200k, cl100k, p50k, p50k_edit, r50k, gpt2, a path to a\nHuggingFace tokenizer.json, or a \"vocab.json,merges.txt\" pair"
     )]
     encoding: Option<String>,
 
+    /// Model the prompt is meant for (selects the encoding and context window)
+    #[clap(
+        short = 'm',
+        long,
+        help = "Model the prompt is meant for (e.g. gpt-4o, claude-3-5-sonnet).\nSelects the encoding and reports how much of its context window is used"
+    )]
+    model: Option<String>,
+
     /// Optional output file path
     #[clap(
         short,
         long,
-        help = "Write final result to this file (instead of / in addition to\ncopying to clipboard)"
+        help = "Write final result to this file (instead of / in addition to\ncopying to clipboard). Use \"-\" to write to stdout"
     )]
     output: Option<String>,
 
+    /// Write the prompt to stdout
+    #[clap(
+        long,
+        help = "Write only the final result to stdout (status messages go to\nstderr), so the output can be piped. Implies --no-clipboard"
+    )]
+    stdout: bool,
+
     /// Include git diff
     #[clap(
         short,
         long,
-        help = "Include git diff (staged changes) from the repo at PATHS"
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "staged",
+        value_parser = PossibleValuesParser::new(["staged", "unstaged", "all"])
+            .try_map(|mode| mode.parse::<DiffMode>()),
+        help = "Include the git diff of uncommitted changes from the repo at PATHS:\nstaged (default), unstaged, or all (including untracked files)"
+    )]
+    diff: Option<DiffMode>,
+
+    /// Context lines in git diffs
+    #[clap(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_DIFF_CONTEXT_LINES,
+        help = "Number of context lines around each change in --diff"
+    )]
+    diff_context: u32,
+
+    /// Generate git diff between two revisions
+    #[clap(
+        long,
+        value_name = "RANGE",
+        value_parser = parse_revision_range,
+        help = "Generate a git diff between two revisions: branches, remote branches,\ntags, SHAs or e.g. HEAD~5 (e.g. --git-diff-branch=\"main,development\").\nUse A...B to diff B against its merge base with A, like a pull request"
     )]
-    diff: bool,
+    git_diff_branch: Option<RevisionRange>,
 
-    /// Generate git diff between two branches
+    /// Retrieve git log between two revisions
     #[clap(
         long,
-        value_name = "BRANCHES",
-        help = "Generate a git diff between two branches (comma-separated)\n(e.g. --git-diff-branch=\"main,development\")"
+        value_name = "RANGE",
+        value_parser = parse_revision_range,
+        help = "Retrieve git log of the commits in B but not in A, for a range\nA,B or A..B or A...B (e.g. --git-log-branch=\"origin/main,HEAD\")"
     )]
-    git_diff_branch: Option<String>,
+    git_log_branch: Option<RevisionRange>,
 
-    /// Retrieve git log between two branches
+    /// Include authors, dates, full messages and changed files in the git log
     #[clap(
         long,
-        value_name = "BRANCHES",
-        help = "Retrieve git log between two branches (comma-separated)\n(e.g. --git-log-branch=\"main,development\")"
+        requires = "git_log_branch",
+        help = "Include the author, date, full message and changed file stats of each commit\nin the git log, and expose the commits to templates as `commits`"
     )]
-    git_log_branch: Option<String>,
+    git_log_details: bool,
 
     /// Add line numbers to the source code
     #[clap(
@@ -107,6 +171,44 @@ struct Cli {
     )]
     line_number: bool,
 
+    /// Annotate each line with its git blame
+    #[clap(
+        long,
+        help = "Annotate each line with the commit, author and date of its last change\n(git blame), combinable with --line-number"
+    )]
+    blame: bool,
+
+    /// Attach the last N commits of each file
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Attach the last N commits that touched each file (SHA, date and summary)\nas its `history`"
+    )]
+    file_history: Option<usize>,
+
+    /// Read the files as of a git revision
+    #[clap(
+        long,
+        value_name = "REF",
+        help = "Read the files as of a git revision (branch, tag or SHA) straight from the\nrepository, without checking it out"
+    )]
+    rev: Option<String>,
+
+    /// Select only the files tracked by git
+    #[clap(
+        long,
+        help = "Select only the files tracked by git, as a fresh clone would have them,\ninstead of walking the directory with the default excludes (--rev only\nreads tracked files anyway)"
+    )]
+    tracked_only: bool,
+
+    /// Also select untracked files that git doesn't ignore
+    #[clap(
+        long,
+        requires = "tracked_only",
+        help = "With --tracked-only, also select the untracked files that aren't ignored by git"
+    )]
+    untracked: bool,
+
     /// Disable wrapping code inside markdown code blocks
     #[clap(long, help = "Do not wrap the code blocks in triple-backtick fences")]
     no_codeblock: bool,
@@ -139,14 +241,49 @@ struct Cli {
     )]
     append: bool,
 
-    /// Optional path to a custom Handlebars template
-    #[clap(short, long, help = "Path to a custom Handlebars template file")]
-    template: Option<PathBuf>,
+    /// Name of a built-in or user template, or path to a custom Handlebars template
+    #[clap(
+        short,
+        long,
+        value_name = "NAME|PATH",
+        help = "Name of a built-in or user template (see --list-templates),\nor path to a custom Handlebars template file"
+    )]
+    template: Option<String>,
+
+    /// Values of user-defined template variables
+    #[clap(
+        long,
+        value_name = "KEY=VALUE",
+        help = "Set a user-defined template variable, instead of being prompted for it.\nUse KEY=@PATH to read the value from a file. Can be repeated"
+    )]
+    var: Vec<String>,
+
+    /// Files holding values of user-defined template variables
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Read user-defined template variables from a .json or .toml file.\nCan be repeated; --var takes precedence"
+    )]
+    var_file: Vec<PathBuf>,
+
+    /// Fail instead of prompting for missing template variables
+    #[clap(
+        long,
+        help = "Fail with the list of missing template variables instead of prompting\nfor them, e.g. in CI"
+    )]
+    no_interactive: bool,
+
+    /// List the available templates
+    #[clap(
+        long,
+        help = "List the built-in and user templates with their descriptions, then exit"
+    )]
+    list_templates: bool,
 
-    /// Print output as JSON (ignored – final output is not printed)
+    /// Output a JSON document with metadata instead of the plain prompt
     #[clap(
         long,
-        help = "(Placeholder) Print output as JSON\n(Currently ignored - no final JSON is printed)"
+        help = "Output a JSON document (source trees, files, token counts, git\nsections and the rendered prompt) instead of the plain prompt"
     )]
     json: bool,
 
@@ -157,19 +294,147 @@ struct Cli {
     )]
     read: bool,
 
-    /// Sampling rate (defaults to 10 if flag present without a value)
+    /// Sample 1 in N files per directory (defaults to 10 if flag present without a value)
     #[clap(
         short = 's',
         long = "sample-rate",
+        num_args = 0..=1,
+        require_equals = true,
         default_missing_value = "10",
-        help = "Provide a sampling rate (integer). Defaults to 10 if the flag is used\nwithout specifying a value."
+        value_parser = clap::value_parser!(u8).range(1..),
+        help = "Keep only 1 in N matching files per directory (sampled\ndeterministically; omitted files are marked in the source tree).\nDefaults to 10 if the flag is used without specifying a value."
     )]
     sample_rate: Option<u8>,
+
+    /// Maximum number of tokens allowed in the final prompt
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Drop or truncate the largest files until the final prompt fits\nin N tokens (the files that were cut are reported)"
+    )]
+    max_tokens: Option<usize>,
+
+    /// Annotate the source tree with token counts
+    #[clap(
+        long,
+        help = "Annotate the source tree with per-file and per-directory token counts"
+    )]
+    tree_tokens: bool,
+
+    /// Print the N largest files by token count
+    #[clap(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "10",
+        help = "Print a summary of the N largest files by token count after the run\n(defaults to 10 if the flag is used without specifying a value)"
+    )]
+    top_files: Option<usize>,
+
+    /// Split the prompt into numbered parts of at most N tokens each
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["json", "file", "append"],
+        help = "Split the prompt into parts of at most N tokens, written to\nprompt-1-of-K.txt, ... (or next to --output). Files are only split\nwhen a single file exceeds N"
+    )]
+    split_tokens: Option<usize>,
+
+    /// Regular expressions file contents must match
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Keep only files whose contents match REGEX. Can be repeated;\na file is kept if any of the patterns matches"
+    )]
+    grep: Vec<String>,
+
+    /// Invert the content filter
+    #[clap(
+        long,
+        requires = "grep",
+        conflicts_with = "grep_context",
+        help = "Keep only files whose contents match none of the --grep patterns"
+    )]
+    grep_invert: bool,
+
+    /// Only include the matching regions of each file
+    #[clap(
+        long,
+        value_name = "N",
+        requires = "grep",
+        help = "Include only the regions of each file matching --grep,\nwith N lines of context around every match"
+    )]
+    grep_context: Option<usize>,
+
+    /// Maximum size of a file to include
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Skip files larger than SIZE, e.g. 500K or 2M (plain numbers are bytes)"
+    )]
+    max_file_size: Option<u64>,
+
+    /// Maximum number of lines of a file to include
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Keep only the first and last lines of files longer than N lines"
+    )]
+    max_file_lines: Option<usize>,
+
+    /// Files to always read as text
+    #[clap(
+        long,
+        value_name = "PATTERNS",
+        help = "Comma-separated patterns of files to always read as text,\neven if their contents look binary"
+    )]
+    force_text: Option<String>,
+
+    /// Files to always treat as binary
+    #[clap(
+        long,
+        value_name = "PATTERNS",
+        help = "Comma-separated patterns of files to always treat as binary\n(listed in the tree, but not read)"
+    )]
+    force_binary: Option<String>,
+
+    /// Encoding of the source files
+    #[clap(
+        long,
+        value_name = "ENCODING",
+        help = "Read files without a byte order mark in this encoding (e.g. latin1,\nwindows-1252, shift_jis, utf-16le) instead of detecting it"
+    )]
+    input_encoding: Option<String>,
+
+    /// Only include the files changed in git
+    #[clap(
+        long,
+        value_name = "REF",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Only include files changed in git: modified in the working tree or\nthe index (including untracked files), or between REF and HEAD if given"
+    )]
+    changed_only: Option<Option<String>>,
+
+    /// Profile from the config file to apply
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "Apply a named profile from .code2prompt.toml or the user config file"
+    )]
+    profile: Option<String>,
 }
 
 fn main() -> Result<()> {
     env_logger::init();
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let args = apply_config(Cli::from_arg_matches(&matches)?, &matches)?;
+
+    if args.list_templates {
+        return print_templates();
+    }
 
     if args.read {
         let spinner = setup_spinner("Reading paths from clipboard...");
@@ -200,13 +465,48 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
         return Err(anyhow::anyhow!("No paths provided."));
     }
 
-    let include_patterns = parse_patterns(&args.include);
-    let exclude_patterns = parse_patterns(&args.exclude);
+    let traverse_options = TraverseOptions {
+        include_patterns: parse_patterns(&args.include),
+        exclude_patterns: parse_patterns(&args.exclude),
+        include_priority: args.include_priority,
+        line_number: args.line_number,
+        relative_paths: args.relative_paths,
+        exclude_from_tree: args.exclude_from_tree,
+        no_codeblock: args.no_codeblock,
+        sample_rate: args.sample_rate,
+        tree_tokens: args.tree_tokens,
+        grep_patterns: args.grep.clone(),
+        grep_invert: args.grep_invert,
+        grep_context: args.grep_context,
+        max_file_size: args.max_file_size,
+        max_file_lines: args.max_file_lines,
+        text_patterns: parse_patterns(&args.force_text),
+        binary_patterns: parse_patterns(&args.force_binary),
+        input_encoding: args.input_encoding.clone(),
+        only_files: None,
+        blame: args.blame,
+        file_history: args.file_history,
+        rev: args.rev.clone(),
+        tracked_only: args.tracked_only,
+        untracked: args.untracked,
+    };
+    let model = args.model.as_deref().map(get_model).transpose()?;
+    let encoding = args
+        .encoding
+        .clone()
+        .or_else(|| model.as_ref().map(|model| model.encoding.to_string()));
+    let tokenizer = get_tokenizer(&encoding)?;
 
-    let (template_content, template_name) = get_template(args)?;
-    let handlebars = handlebars_setup(&template_content, template_name)?;
+    let template = find_template(
+        args.template.as_deref().unwrap_or(DEFAULT_TEMPLATE_NAME),
+        user_template_dir().as_deref(),
+    )?;
+    let handlebars = handlebars_setup(&template.content, &template.name)?;
+    let template_vars = load_template_variables(&args.var, &args.var_file)?;
 
-    let mut folder_outputs = Vec::new();
+    let mut folder_tags = Vec::new();
+    let mut folder_data = Vec::new();
+    let mut limited_files = Vec::new();
     for folder in paths {
         if !folder.exists() {
             eprintln!(
@@ -220,51 +520,96 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
         }
 
         let spinner = setup_spinner(&format!("Processing {}...", folder.display()));
-        let (full_tree, all_files) = traverse_directory(
-            folder,
-            &include_patterns,
-            &exclude_patterns,
-            args.include_priority,
-            args.line_number,
-            args.relative_paths,
-            args.exclude_from_tree,
-            args.no_codeblock,
-        )?;
-
-        let (git_diff, git_diff_branch, git_log_branch) = if folder.is_dir() {
-            let git_diff = if args.diff {
+        let (full_tree, all_files, limited) = match &args.changed_only {
+            Some(since) => {
+                spinner.set_message("Finding changed files...");
+                let folder_options = TraverseOptions {
+                    only_files: Some(get_changed_files(folder, since.as_deref())?),
+                    ..traverse_options.clone()
+                };
+                traverse_directory(folder, &folder_options, &tokenizer)?
+            }
+            None => traverse_directory(folder, &traverse_options, &tokenizer)?,
+        };
+        limited_files.extend(limited);
+
+        let git_data = if folder.is_dir() {
+            let mut git_diffs = [String::new(), String::new(), String::new()];
+            let mut diff_files = Vec::new();
+            if let Some(selected) = args.diff {
                 spinner.set_message("Generating git diff...");
-                get_git_diff(folder).unwrap_or_default()
-            } else {
-                String::new()
-            };
+                // The other modes are only generated if the template uses them
+                for (diff, (mode, variable)) in git_diffs.iter_mut().zip(DIFF_MODES) {
+                    if mode == selected {
+                        (*diff, diff_files) = or_warn(
+                            get_working_tree_diff_and_files(folder, mode, args.diff_context),
+                            "Failed to generate git diff",
+                            &spinner,
+                        );
+                    } else if template.content.contains(variable) {
+                        *diff = or_warn(
+                            get_working_tree_diff(folder, mode, args.diff_context),
+                            &format!("Failed to generate {}", variable),
+                            &spinner,
+                        );
+                    }
+                }
+            }
 
             let mut git_diff_branch = String::new();
-            if let Some(branches) = &args.git_diff_branch {
-                spinner.set_message("Generating git diff between branches...");
-                let branches = parse_patterns(&Some(branches.to_string()));
-                if branches.len() != 2 {
-                    error!("Please provide exactly two branches separated by a comma.");
-                    std::process::exit(1);
-                }
-                git_diff_branch = get_git_diff_between_branches(folder, &branches[0], &branches[1])
-                    .unwrap_or_default();
+            let mut diff_files_branch = Vec::new();
+            if let Some(range) = &args.git_diff_branch {
+                spinner.set_message("Generating git diff between revisions...");
+                (git_diff_branch, diff_files_branch) = or_warn(
+                    get_git_diff_and_files_between_revisions(folder, range),
+                    "Failed to generate git diff between revisions",
+                    &spinner,
+                );
             }
 
             let mut git_log_branch = String::new();
-            if let Some(branches) = &args.git_log_branch {
-                spinner.set_message("Generating git log between branches...");
-                let branches = parse_patterns(&Some(branches.to_string()));
-                if branches.len() != 2 {
-                    error!("Please provide exactly two branches separated by a comma.");
-                    std::process::exit(1);
+            let mut commits = Vec::new();
+            if let Some(range) = &args.git_log_branch {
+                spinner.set_message("Generating git log between revisions...");
+                if args.git_log_details {
+                    commits = or_warn(
+                        get_git_commits(folder, range),
+                        "Failed to generate git log between revisions",
+                        &spinner,
+                    );
+                    git_log_branch = format_commits(&commits);
+                } else {
+                    git_log_branch = or_warn(
+                        get_git_log_between_revisions(folder, range),
+                        "Failed to generate git log between revisions",
+                        &spinner,
+                    );
                 }
-                git_log_branch =
-                    get_git_log(folder, &branches[0], &branches[1]).unwrap_or_default();
             }
-            (git_diff, git_diff_branch, git_log_branch)
+            let commits: Vec<serde_json::Value> =
+                commits.iter().map(|commit| commit.to_json()).collect();
+            let to_json = |files: Vec<DiffFile>| -> Vec<serde_json::Value> {
+                files.iter().map(|file| file.to_json()).collect()
+            };
+            (
+                git_diffs,
+                to_json(diff_files),
+                git_diff_branch,
+                to_json(diff_files_branch),
+                git_log_branch,
+                commits,
+            )
         } else {
-            (String::new(), String::new(), String::new())
+            Default::default()
+        };
+        let (git_diffs, diff_files, git_diff_branch, diff_files_branch, git_log_branch, commits) =
+            git_data;
+        let [git_diff_staged, git_diff_unstaged, git_diff_all] = git_diffs;
+        let git_diff = match args.diff {
+            Some(DiffMode::Staged) => git_diff_staged.clone(),
+            Some(DiffMode::Unstaged) => git_diff_unstaged.clone(),
+            Some(DiffMode::All) => git_diff_all.clone(),
+            None => String::new(),
         };
 
         let mut data = json!({
@@ -272,8 +617,14 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
             "source_tree": full_tree,
             "files": all_files,
             "git_diff": git_diff,
+            "git_diff_staged": git_diff_staged,
+            "git_diff_unstaged": git_diff_unstaged,
+            "git_diff_all": git_diff_all,
+            "diff_files": diff_files,
             "git_diff_branch": git_diff_branch,
-            "git_log_branch": git_log_branch
+            "diff_files_branch": diff_files_branch,
+            "git_log_branch": git_log_branch,
+            "commits": commits
         });
 
         debug!(
@@ -282,17 +633,15 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
             serde_json::to_string_pretty(&data)?
         );
 
-        spinner.set_message("Rendering prompt...");
-        handle_undefined_variables(&mut data, &template_content)?;
-        let rendered = render_template(&handlebars, template_name, &data)?;
-
-        let folder_tag = code2prompt::path::label(folder);
-        let wrapped = format!(
-            "<{tag}>\n{indented}\n</{tag}>",
-            tag = folder_tag,
-            indented = indent(&rendered, 2)
-        );
-        folder_outputs.push(wrapped);
+        // The built-in data can't be replaced by template variables
+        if let Some(object) = data.as_object_mut() {
+            for (key, value) in &template_vars {
+                object.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        handle_undefined_variables(&mut data, &template.content, !args.no_interactive)?;
+        folder_tags.push(label(folder));
+        folder_data.push(data);
         spinner.finish_with_message(
             format!("Processed {}.", folder.display())
                 .green()
@@ -300,15 +649,60 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
         );
     }
 
-    let final_output = format!("<context>\n{}\n</context>", folder_outputs.join("\n\n"));
+    let render_context = |folders: &[serde_json::Value]| -> Result<String> {
+        let mut folder_outputs = Vec::new();
+        for (tag, data) in folder_tags.iter().zip(folders) {
+            let rendered = render_template(&handlebars, &template.name, data)?;
+            folder_outputs.push(format!(
+                "<{tag}>\n{indented}\n</{tag}>",
+                tag = tag,
+                indented = indent(&rendered, 2)
+            ));
+        }
+        Ok(format!(
+            "<context>\n{}\n</context>",
+            folder_outputs.join("\n\n")
+        ))
+    };
 
-    let token_count = {
-        let bpe = get_tokenizer(&args.encoding);
-        bpe.encode_with_special_tokens(&final_output).len()
+    print_limited_files(&limited_files);
+
+    let (final_output, cuts) = if let Some(max_tokens) = args.max_tokens {
+        let (output, cuts) =
+            fit_to_token_budget(&mut folder_data, max_tokens, &tokenizer, &render_context)?;
+        for cut in &cuts {
+            let action = if cut.kept_tokens == 0 {
+                format!("dropped ({} tokens)", cut.original_tokens)
+            } else {
+                format!(
+                    "truncated ({} of {} tokens kept)",
+                    cut.kept_tokens, cut.original_tokens
+                )
+            };
+            eprintln!(
+                "{}{}{} Token budget: {} {}",
+                "[".bold().white(),
+                "i".bold().blue(),
+                "]".bold().white(),
+                cut.path,
+                action.yellow()
+            );
+        }
+        (output, cuts)
+    } else {
+        (render_context(&folder_data)?, Vec::new())
     };
 
-    let model_info = get_model_info(&args.encoding);
-    println!(
+    let token_count = tokenizer.count(&final_output)?;
+
+    let mut model_info = get_model_info(&encoding, model.as_ref(), token_count);
+    if model
+        .as_ref()
+        .is_some_and(|model| token_count > model.context_window)
+    {
+        model_info = model_info.red().to_string();
+    }
+    eprintln!(
         "{}{}{} Token count: {}, Model info: {}",
         "[".bold().white(),
         "i".bold().blue(),
@@ -317,6 +711,65 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
         model_info
     );
 
+    if let Some(top) = args.top_files {
+        print_largest_files(&folder_data, top);
+    }
+
+    let to_stdout = args.stdout || args.output.as_deref() == Some("-");
+
+    if let Some(split_tokens) = args.split_tokens {
+        if to_stdout {
+            return Err(anyhow::anyhow!(
+                "--split-tokens writes one file per part and can't write to stdout"
+            ));
+        }
+        let parts = split_into_parts(&folder_data, split_tokens, &tokenizer, &render_context)?;
+        let output_path = args.output.as_deref().unwrap_or("prompt.txt");
+        for (index, part) in parts.iter().enumerate() {
+            // Only a part holding a single piece that can't be split further ends up here
+            let part_tokens = tokenizer.count(part)?;
+            if part_tokens > split_tokens {
+                eprintln!(
+                    "{}{}{} {}",
+                    "[".bold().white(),
+                    "!".bold().yellow(),
+                    "]".bold().white(),
+                    format!(
+                        "Part {} of {} is {} tokens, which exceeds the limit of {}",
+                        index + 1,
+                        parts.len(),
+                        part_tokens,
+                        split_tokens
+                    )
+                    .yellow()
+                );
+            }
+            write_to_file(&part_file_path(output_path, index + 1, parts.len()), part)?;
+        }
+        eprintln!(
+            "{}{}{} {}",
+            "[".bold().white(),
+            "✓".bold().green(),
+            "]".bold().white(),
+            format!("Done. Prompt split into {} parts.", parts.len()).green()
+        );
+        return Ok(());
+    }
+
+    let final_output = if args.json {
+        let document = build_json_output(
+            &encoding,
+            model.as_ref(),
+            &folder_data,
+            &cuts,
+            &final_output,
+            token_count,
+        );
+        serde_json::to_string_pretty(&document)?
+    } else {
+        final_output
+    };
+
     // Optionally materialize the output into a context.txt file
     let mut context_file_path: Option<PathBuf> = None;
     if args.file {
@@ -327,7 +780,7 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
     // Clipboard behaviour:
     // - default: copy text as before
     // - with --file: copy context.txt as a file (macOS/Windows) or fall back to text
-    if !args.no_clipboard {
+    if !args.no_clipboard && !to_stdout {
         if args.file {
             if let Some(path) = &context_file_path {
                 if let Err(e) = copy_file_to_clipboard(path) {
@@ -339,7 +792,7 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
                         format!("Failed to copy context.txt file to clipboard: {}", e).red()
                     );
                 } else {
-                    println!(
+                    eprintln!(
                         "{}{}{} {}",
                         "[".bold().white(),
                         "✓".bold().green(),
@@ -362,7 +815,7 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
                     format!("Failed to copy to clipboard: {}", e).red()
                 );
             } else {
-                println!(
+                eprintln!(
                     "{}{}{} {}",
                     "[".bold().white(),
                     "✓".bold().green(),
@@ -377,11 +830,15 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
         }
     }
 
-    if let Some(output_path) = &args.output {
+    if let Some(output_path) = args.output.as_deref().filter(|path| *path != "-") {
         write_to_file(output_path, &final_output)?;
     }
 
-    println!(
+    if to_stdout {
+        write_to_stdout(&final_output)?;
+    }
+
+    eprintln!(
         "{}{}{} {}",
         "[".bold().white(),
         "✓".bold().green(),
@@ -392,6 +849,223 @@ fn process_paths(paths: &[PathBuf], args: &Cli) -> Result<()> {
     Ok(())
 }
 
+/// Builds the `--json` document: every root's tree, files and git sections,
+/// along with token counts and the rendered prompt.
+fn build_json_output(
+    encoding: &Option<String>,
+    model: Option<&ModelInfo>,
+    folder_data: &[serde_json::Value],
+    cuts: &[BudgetCut],
+    prompt: &str,
+    token_count: usize,
+) -> serde_json::Value {
+    let roots: Vec<serde_json::Value> = folder_data
+        .iter()
+        .map(|data| {
+            let file_tokens: u64 = data["files"]
+                .as_array()
+                .map(|files| files.iter().filter_map(|f| f["tokens"].as_u64()).sum())
+                .unwrap_or(0);
+
+            json!({
+                "path": data["absolute_code_path"],
+                "source_tree": data["source_tree"],
+                "files": data["files"],
+                "file_token_count": file_tokens,
+                "git_diff": data["git_diff"],
+                "git_diff_staged": data["git_diff_staged"],
+                "git_diff_unstaged": data["git_diff_unstaged"],
+                "git_diff_all": data["git_diff_all"],
+                "diff_files": data["diff_files"],
+                "git_diff_branch": data["git_diff_branch"],
+                "diff_files_branch": data["diff_files_branch"],
+                "git_log_branch": data["git_log_branch"],
+                "commits": data["commits"],
+            })
+        })
+        .collect();
+
+    let cut_files: Vec<serde_json::Value> = cuts
+        .iter()
+        .map(|cut| {
+            json!({
+                "path": cut.path,
+                "original_tokens": cut.original_tokens,
+                "kept_tokens": cut.kept_tokens,
+            })
+        })
+        .collect();
+
+    json!({
+        "encoding": encoding.as_deref().unwrap_or("cl100k"),
+        "model": model.map(|model| model.name.as_str()),
+        "context_window": model.map(|model| model.context_window),
+        "model_info": get_model_info(encoding, model, token_count),
+        "token_count": token_count,
+        "roots": roots,
+        "cut_files": cut_files,
+        "prompt": prompt,
+    })
+}
+
+/// Prints the `top` largest files across all roots, by token count.
+fn print_largest_files(folder_data: &[serde_json::Value], top: usize) {
+    let mut files: Vec<(&str, u64)> = folder_data
+        .iter()
+        .filter_map(|data| data["files"].as_array())
+        .flatten()
+        .map(|file| {
+            (
+                file["path"].as_str().unwrap_or(""),
+                file["tokens"].as_u64().unwrap_or(0),
+            )
+        })
+        .collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    eprintln!(
+        "{}{}{} Largest files:",
+        "[".bold().white(),
+        "i".bold().blue(),
+        "]".bold().white(),
+    );
+    for (path, tokens) in files.into_iter().take(top) {
+        eprintln!("    {:>8}  {}", tokens.to_string().bold().yellow(), path);
+    }
+}
+
+/// Prints the files that were skipped or truncated because of `--max-file-size`
+/// or `--max-file-lines`.
+fn print_limited_files(limited_files: &[LimitedFile]) {
+    let (skipped, truncated): (Vec<_>, Vec<_>) = limited_files
+        .iter()
+        .partition(|file| matches!(file, LimitedFile::Skipped { .. }));
+
+    if !skipped.is_empty() {
+        eprintln!(
+            "{}{}{} Skipped {} file(s) larger than --max-file-size:",
+            "[".bold().white(),
+            "i".bold().blue(),
+            "]".bold().white(),
+            skipped.len()
+        );
+        for file in skipped {
+            if let LimitedFile::Skipped { path, size } = file {
+                eprintln!("    {:>10}  {}", format_size(*size).bold().yellow(), path);
+            }
+        }
+    }
+
+    if !truncated.is_empty() {
+        eprintln!(
+            "{}{}{} Truncated {} file(s) longer than --max-file-lines:",
+            "[".bold().white(),
+            "i".bold().blue(),
+            "]".bold().white(),
+            truncated.len()
+        );
+        for file in truncated {
+            if let LimitedFile::Truncated {
+                path,
+                total_lines,
+                kept_lines,
+            } = file
+            {
+                eprintln!(
+                    "    {:>10}  {} ({} lines kept)",
+                    format!("{} lines", total_lines).bold().yellow(),
+                    path,
+                    kept_lines
+                );
+            }
+        }
+    }
+}
+
+/// Returns the result of a git operation, or prints a warning and returns an empty value if
+/// it failed, e.g. because the path isn't in a git repository.
+///
+/// # Arguments
+///
+/// * `result` - The result of the git operation.
+/// * `message` - What failed, printed before the error.
+/// * `spinner` - The spinner to hide while printing.
+///
+/// # Returns
+///
+/// * `T` - The value of the result, or its default.
+fn or_warn<T: Default>(result: Result<T>, message: &str, spinner: &ProgressBar) -> T {
+    result.unwrap_or_else(|err| {
+        spinner.suspend(|| {
+            eprintln!(
+                "{}{}{} {}",
+                "[".bold().white(),
+                "!".bold().yellow(),
+                "]".bold().white(),
+                format!("{}: {:#}", message, err).yellow()
+            )
+        });
+        T::default()
+    })
+}
+
+/// Parses a revision range such as `main,feature`, `v1.0..HEAD` or `origin/main...HEAD`.
+fn parse_revision_range(range: &str) -> std::result::Result<RevisionRange, String> {
+    range.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+/// Parses a file size such as `500`, `500K`, `2MB` or `1G` into bytes.
+fn parse_size(size: &str) -> std::result::Result<u64, String> {
+    let size = size.trim();
+    let digits_end = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits_end);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit '{}' (use B, K, M or G)", unit)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Writes the final output to stdout, treating a closed pipe (e.g. `| head`) as success.
+fn write_to_stdout(rendered: &str) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    match writeln!(stdout, "{}", rendered).and_then(|_| stdout.flush()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+            Err(e).context("Failed to write to stdout")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the file path of one part of a split prompt, e.g. `prompt-2-of-3.txt`
+/// for `prompt.txt`.
+fn part_file_path(output_path: &str, part: usize, total: usize) -> String {
+    let path = std::path::Path::new(output_path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_else(|| "prompt".into());
+    let file_name = match path.extension() {
+        Some(extension) => format!(
+            "{}-{}-of-{}.{}",
+            stem,
+            part,
+            total,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}-{}-of-{}", stem, part, total),
+    };
+    path.with_file_name(file_name).display().to_string()
+}
+
 /// Indents each line of a multiline string by a given number of spaces.
 fn indent(text: &str, spaces: usize) -> String {
     let pad = " ".repeat(spaces);
@@ -439,7 +1113,7 @@ fn write_context_file(rendered: &str) -> Result<PathBuf> {
     fs::write(&context_path, rendered)
         .with_context(|| format!("Failed to write context file at {}", context_path.display()))?;
 
-    println!(
+    eprintln!(
         "{}{}{} {}",
         "[".bold().white(),
         "✓".bold().green(),
@@ -450,6 +1124,173 @@ fn write_context_file(rendered: &str) -> Result<PathBuf> {
     Ok(context_path)
 }
 
+/// Applies the settings from the user and repository config files to the CLI arguments.
+///
+/// The repository config is the closest `.code2prompt.toml` above the first path (or the
+/// current directory), and overrides the user config. Options given on the command line
+/// always win over both.
+///
+/// # Arguments
+///
+/// * `args` - The arguments parsed from the command line.
+/// * `matches` - The matches the arguments were parsed from.
+///
+/// # Returns
+///
+/// * `Result<Cli>` - The arguments with the config settings applied.
+fn apply_config(args: Cli, matches: &ArgMatches) -> Result<Cli> {
+    let start_dir = match args.paths.first() {
+        Some(path) => path.clone(),
+        None => std::env::current_dir().context("Failed to get the current directory")?,
+    };
+    let config_files: Vec<PathBuf> = user_config_path()
+        .into_iter()
+        .chain(find_repo_config(&start_dir))
+        .filter(|path| path.is_file())
+        .collect();
+    if config_files.is_empty() {
+        return match &args.profile {
+            Some(profile) => Err(anyhow::anyhow!(
+                "Profile '{}' requested but no config file was found",
+                profile
+            )),
+            None => Ok(args),
+        };
+    }
+
+    let settings = load_config(&config_files, args.profile.as_deref())?;
+    let config_args = config_to_args(&settings, matches)?;
+    for path in &config_files {
+        eprintln!(
+            "{}{}{} {}",
+            "[".bold().white(),
+            "i".bold().blue(),
+            "]".bold().white(),
+            format!("Using config file: {}", path.display()).blue()
+        );
+    }
+    if config_args.is_empty() {
+        return Ok(args);
+    }
+
+    debug!("Config arguments: {:?}", config_args);
+    let configured: Vec<String> = settings.keys().map(|key| key.replace('-', "_")).collect();
+    let config_matches = config_command(&configured)
+        .try_get_matches_from(config_args)
+        .unwrap_or_else(|e| e.exit());
+    let mut args = args;
+    args.update_from_arg_matches(&config_matches)?;
+    Ok(args)
+}
+
+/// Builds the command that parses the config settings on their own, so they can be merged
+/// into the arguments from the command line.
+///
+/// The options that aren't set in the config get no default value, so that merging leaves
+/// them as they were on the command line. Requirements and conflicts between options are
+/// only checked on the command line.
+///
+/// # Arguments
+///
+/// * `configured` - The ids of the options set in the config.
+///
+/// # Returns
+///
+/// * `clap::Command` - The command to parse the arguments built by `config_to_args` with.
+fn config_command(configured: &[String]) -> clap::Command {
+    Cli::command()
+        .no_binary_name(true)
+        .arg_required_else_help(false)
+        .mut_args(|arg| {
+            let arg = arg
+                .required(false)
+                .required_unless_present(Resettable::Reset)
+                .requires(Resettable::Reset)
+                .conflicts_with(Resettable::Reset);
+            if configured.iter().any(|id| arg.get_id() == id.as_str()) {
+                arg
+            } else {
+                arg.action(ArgAction::Set).default_value(None)
+            }
+        })
+}
+
+/// Converts config settings into arguments for `config_command`, skipping the options that
+/// were already given on the command line.
+///
+/// # Arguments
+///
+/// * `settings` - The resolved config settings, keyed by option name.
+/// * `matches` - The matches parsed from the command line.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>>` - The arguments to parse.
+fn config_to_args(settings: &toml::Table, matches: &ArgMatches) -> Result<Vec<String>> {
+    let command = Cli::command();
+    let mut config_args = Vec::new();
+
+    for (key, value) in settings {
+        let id = key.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+            .filter(|_| !CLI_ONLY_OPTIONS.contains(&id.as_str()))
+            .ok_or_else(|| anyhow::anyhow!("Unknown option '{}' in config file", key))?;
+        if matches.value_source(&id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let long = arg.get_long().expect("config options all have a long name");
+
+        // Repeatable options take one argument per list item
+        if let (ArgAction::Append, toml::Value::Array(items)) = (arg.get_action(), value) {
+            for item in items {
+                match item {
+                    toml::Value::String(item) => config_args.push(format!("--{}={}", long, item)),
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Option '{}' in config file must be a list of strings",
+                            key
+                        ))
+                    }
+                }
+            }
+            continue;
+        }
+
+        let value = match value {
+            toml::Value::Boolean(true) => {
+                config_args.push(format!("--{}", long));
+                continue;
+            }
+            toml::Value::Boolean(false) => continue,
+            toml::Value::String(value) => value.clone(),
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(item) => Ok(item.clone()),
+                    _ => Err(anyhow::anyhow!(
+                        "Option '{}' in config file must be a list of strings",
+                        key
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?
+                .join(","),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported value for option '{}' in config file",
+                    key
+                ))
+            }
+        };
+        config_args.push(format!("--{}={}", long, value));
+    }
+
+    Ok(config_args)
+}
+
 /// Parses comma-separated patterns into a vector of strings.
 fn parse_patterns(patterns: &Option<String>) -> Vec<String> {
     match patterns {
@@ -476,16 +1317,24 @@ fn parse_patterns(patterns: &Option<String>) -> Vec<String> {
     }
 }
 
-/// Retrieves the template content and name based on CLI arguments.
-fn get_template(args: &Cli) -> Result<(String, &str)> {
-    if let Some(template_path) = &args.template {
-        let content =
-            fs::read_to_string(template_path).context("Failed to read custom template file")?;
-        Ok((content, CUSTOM_TEMPLATE_NAME))
-    } else {
-        Ok((
-            include_str!("default_template.hbs").to_string(),
-            DEFAULT_TEMPLATE_NAME,
-        ))
+/// Prints the built-in and user templates with their descriptions, for `--list-templates`.
+fn print_templates() -> Result<()> {
+    let templates = list_templates(user_template_dir().as_deref())?;
+    let labels: Vec<String> = templates
+        .iter()
+        .map(|template| match template.source {
+            TemplateSource::User(_) => format!("{} (user)", template.name),
+            _ => template.name.clone(),
+        })
+        .collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(0);
+    for (template, label) in templates.iter().zip(labels) {
+        println!(
+            "{:<width$}  {}",
+            label,
+            template.description().unwrap_or_default(),
+            width = width
+        );
     }
+    Ok(())
 }
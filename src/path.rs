@@ -1,20 +1,42 @@
 //! This module contains the functions for traversing the directory and processing the files.
 
-use anyhow::Result;
+use crate::binary::{binary_type, is_binary_as, SNIFF_LEN};
+use crate::charset::{decode_text, encoding_for_label};
+use crate::git::{get_tracked_files, Blamer, FileHistory, LineBlame, Snapshot, SnapshotEntry};
+use crate::token::Tokenizer;
+use anyhow::{Context, Result};
+use colored::*;
+use encoding_rs::Encoding;
+use git2::Oid;
 use glob::Pattern;
-use ignore::overrides::OverrideBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::Match;
 use ignore::WalkBuilder;
 use lazy_static::lazy_static;
 use log::debug;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use regex::Regex;
 use serde_json::json;
-use std::{fs, path::Path};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
 use termtree::Tree;
 
 lazy_static! {
     static ref BASE64_REGEX: Regex = Regex::new(r#"(?P<b64>[A-Za-z0-9+/=]{80,})"#).unwrap();
 }
 
+/// Fixed seed for `--sample-rate`, so the same tree always yields the same sample.
+const SAMPLE_SEED: u64 = 0xc0de_2920_4d70_5eed;
+
+/// Label appended to files in the source tree that were left out by sampling.
+const SAMPLED_OUT_MARKER: &str = " [sampled out]";
+
 /// Shortens all base64 strings longer than 80 chars
 ///
 /// # Arguments
@@ -39,76 +61,402 @@ pub fn shorten_long_base64_strings(code: &str) -> String {
         .to_string()
 }
 
+/// Options controlling which files `traverse_directory` selects and how it renders them.
+#[derive(Debug, Clone, Default)]
+pub struct TraverseOptions {
+    /// The patterns of files to include.
+    pub include_patterns: Vec<String>,
+    /// The patterns of files to exclude.
+    pub exclude_patterns: Vec<String>,
+    /// Whether to give priority to include patterns.
+    pub include_priority: bool,
+    /// Whether to add line numbers to the code.
+    pub line_number: bool,
+    /// Whether to use relative paths.
+    pub relative_paths: bool,
+    /// Whether to exclude files from the tree.
+    pub exclude_from_tree: bool,
+    /// Whether to not wrap the code block with a delimiter.
+    pub no_codeblock: bool,
+    /// Keep only 1 in N of the selected files in each directory.
+    pub sample_rate: Option<u8>,
+    /// Whether to annotate the tree with per-file and per-directory token counts.
+    pub tree_tokens: bool,
+    /// Regular expressions of which at least one must match a file's contents.
+    pub grep_patterns: Vec<String>,
+    /// Whether to keep the files matching none of the grep patterns instead.
+    pub grep_invert: bool,
+    /// Keep only the matching regions of each file, with this many lines of context.
+    pub grep_context: Option<usize>,
+    /// Skip files larger than this many bytes.
+    pub max_file_size: Option<u64>,
+    /// Keep only the first and last lines of files longer than this many lines.
+    pub max_file_lines: Option<usize>,
+    /// The patterns of files to always read as text, even if they look binary.
+    pub text_patterns: Vec<String>,
+    /// The patterns of files to always treat as binary.
+    pub binary_patterns: Vec<String>,
+    /// The encoding of files without a byte order mark, instead of detecting it.
+    pub input_encoding: Option<String>,
+    /// If set, only these files (absolute paths) are selected, e.g. the files changed in git.
+    pub only_files: Option<HashSet<PathBuf>>,
+    /// Whether to annotate each line with the commit, author and date of its last change.
+    pub blame: bool,
+    /// Attach this many of the last commits that touched each file as its `history`.
+    pub file_history: Option<usize>,
+    /// Read the files as of this git revision, from the object database instead of the disk.
+    pub rev: Option<String>,
+    /// Whether to select only the files tracked by git, instead of walking the directory.
+    pub tracked_only: bool,
+    /// Whether to also select the untracked files that git doesn't ignore, with `tracked_only`.
+    pub untracked: bool,
+}
+
+/// A file that was skipped or truncated because of `max_file_size` or `max_file_lines`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitedFile {
+    /// The file was left out because it is larger than `max_file_size`.
+    Skipped { path: String, size: u64 },
+    /// The file was cut down to its first and last lines.
+    Truncated {
+        path: String,
+        total_lines: usize,
+        kept_lines: usize,
+    },
+}
+
+/// The outcome of loading a selected file.
+enum LoadedFile {
+    /// The code to include, whether line numbers (if requested) were already added,
+    /// the original line count if the file was truncated, and the encoding the code
+    /// was converted from if it wasn't UTF-8.
+    Kept {
+        code: String,
+        numbered: bool,
+        truncated_from: Option<usize>,
+        encoding: Option<&'static str>,
+    },
+    /// The contents didn't pass the grep filter.
+    Rejected,
+    /// The file is larger than `max_file_size`.
+    TooLarge(u64),
+    /// The file is binary, with its detected type and size.
+    Binary(&'static str, u64),
+    /// The file couldn't be read.
+    Unreadable,
+}
+
+/// Prefixes the lines of a file with their line number, their git blame, or both.
+struct LineAnnotator {
+    line_number: bool,
+    /// The formatted blame of each line of the file, all of the same width.
+    blame: Option<Vec<String>>,
+}
+
+impl LineAnnotator {
+    /// Prepares the annotations of a file, formatting its blame as `sha (author date)`.
+    fn new(line_number: bool, blame: Option<Vec<LineBlame>>) -> Self {
+        let blame = blame.map(|lines| {
+            let author_width = lines
+                .iter()
+                .map(|line| line.author.chars().count())
+                .max()
+                .unwrap_or(0);
+            lines
+                .iter()
+                .map(|line| {
+                    format!(
+                        "{} ({:<author_width$} {:<10})",
+                        line.short_sha,
+                        line.author,
+                        line.date,
+                        author_width = author_width
+                    )
+                })
+                .collect()
+        });
+        LineAnnotator { line_number, blame }
+    }
+
+    /// Whether lines get any annotation at all.
+    fn is_active(&self) -> bool {
+        self.line_number || self.blame.is_some()
+    }
+
+    /// Annotates the line with the given index, counting from 0.
+    fn annotate(&self, index: usize, line: &str) -> String {
+        let mut prefix = Vec::new();
+        if let Some(blame) = &self.blame {
+            // Lines past the blamed ones (e.g. after decoding) get an empty column
+            let width = blame.first().map_or(0, |first| first.chars().count());
+            prefix.push(
+                blame
+                    .get(index)
+                    .cloned()
+                    .unwrap_or_else(|| " ".repeat(width)),
+            );
+        }
+        if self.line_number {
+            prefix.push(format!("{:4}", index + 1));
+        }
+        format!("{} | {}\n", prefix.join(" "), line)
+    }
+
+    /// Annotates every line of the code.
+    fn annotate_all(&self, code: &str) -> String {
+        let lines: Vec<&str> = code.lines().collect();
+        join_kept_lines(&lines, &vec![true; lines.len()], self, |_| String::new())
+    }
+}
+
+/// A file or directory found by the traversal, on disk or in a git revision.
+struct Entry {
+    /// The absolute path, as it is (or would be) on disk.
+    path: PathBuf,
+    is_dir: bool,
+    /// The blob holding the contents of a file read from a git revision.
+    blob: Option<Oid>,
+}
+
+/// Where the contents of a file are read from.
+enum FileSource<'a> {
+    /// The file on disk.
+    Disk,
+    /// A blob of a git revision.
+    Blob(&'a Snapshot, Oid),
+}
+
+/// Content filter built from the grep options of `TraverseOptions`.
+struct GrepFilter {
+    regexes: Vec<Regex>,
+    invert: bool,
+    context: Option<usize>,
+}
+
+impl GrepFilter {
+    /// Compiles the grep patterns, returning `None` when there are none.
+    fn new(options: &TraverseOptions) -> Result<Option<Self>> {
+        if options.grep_patterns.is_empty() {
+            return Ok(None);
+        }
+        let regexes = options
+            .grep_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("Invalid grep pattern '{}'", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(GrepFilter {
+            regexes,
+            invert: options.grep_invert,
+            context: options.grep_context,
+        }))
+    }
+
+    /// Applies the filter to the contents of a file.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(String, bool)>` - `None` if the file is filtered out, otherwise the code to
+    ///   keep and whether its lines have already been annotated.
+    fn apply(&self, code: &str, annotator: &LineAnnotator) -> Option<(String, bool)> {
+        let matches = self.regexes.iter().any(|regex| regex.is_match(code));
+        if matches == self.invert {
+            return None;
+        }
+        match self.context {
+            Some(context) if !self.invert => Some((self.excerpt(code, context, annotator), true)),
+            _ => Some((code.to_string(), false)),
+        }
+    }
+
+    /// Keeps only the lines around matches, replacing the lines in between with a marker.
+    fn excerpt(&self, code: &str, context: usize, annotator: &LineAnnotator) -> String {
+        let lines: Vec<&str> = code.lines().collect();
+        if lines.is_empty() {
+            return String::new();
+        }
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let line_of = |offset: usize| {
+            (line_starts.partition_point(|&start| start <= offset) - 1).min(lines.len() - 1)
+        };
+
+        let mut keep = vec![false; lines.len()];
+        for regex in &self.regexes {
+            for m in regex.find_iter(code) {
+                let first = line_of(m.start());
+                let last = line_of(m.end().saturating_sub(1).max(m.start()));
+                let end = (last + context).min(lines.len() - 1);
+                keep[first.saturating_sub(context)..=end].fill(true);
+            }
+        }
+
+        join_kept_lines(&lines, &keep, annotator, |omitted| {
+            format!("... [{} lines omitted] ...", omitted)
+        })
+    }
+}
+
 /// Traverses the directory and returns the string representation of the tree and the vector of JSON file representations.
 ///
 /// # Arguments
 ///
 /// * `root_path` - The path to the root directory.
-/// * `include_patterns` - The patterns of files to include.
-/// * `exclude_patterns` - The patterns of files to exclude.
-/// * `_include_priority` - Whether to give priority to include patterns (deprecated).
-/// * `line_number` - Whether to add line numbers to the code.
-/// * `relative_paths` - Whether to use relative paths.
-/// * `exclude_from_tree` - Whether to exclude files from the tree.
-/// * `no_codeblock` - Whether to not wrap the code block with a delimiter.
+/// * `options` - The selection and rendering options.
+/// * `tokenizer` - The tokenizer used to count the tokens of each file.
+///
 /// # Returns
 ///
-/// A tuple containing the string representation of the directory tree and a vector of JSON representations of the files.
-#[allow(clippy::too_many_arguments)]
+/// A tuple containing the string representation of the directory tree, a vector of JSON representations of the files,
+/// and the files that were skipped or truncated because of the file size limits.
 pub fn traverse_directory(
     root_path: &Path,
-    include_patterns: &[String],
-    exclude_patterns: &[String],
-    include_priority: bool,
-    line_number: bool,
-    relative_paths: bool,
-    exclude_from_tree: bool,
-    no_codeblock: bool,
-) -> Result<(String, Vec<serde_json::Value>)> {
+    options: &TraverseOptions,
+    tokenizer: &Tokenizer,
+) -> Result<(String, Vec<serde_json::Value>, Vec<LimitedFile>)> {
+    let TraverseOptions {
+        ref include_patterns,
+        ref exclude_patterns,
+        include_priority,
+        line_number,
+        relative_paths,
+        exclude_from_tree,
+        no_codeblock,
+        sample_rate,
+        tree_tokens,
+        ..
+    } = *options;
+    let grep = GrepFilter::new(options)?;
+    let input_encoding = options
+        .input_encoding
+        .as_deref()
+        .map(encoding_for_label)
+        .transpose()?;
+    let rev = options.rev.as_deref();
+    let blamer = if options.blame {
+        Some(Blamer::discover(root_path, rev).context("--blame only works in a git repository")?)
+    } else {
+        None
+    };
+    let file_history = options
+        .file_history
+        .map(|limit| {
+            FileHistory::discover(root_path, rev, limit)
+                .context("--file-history only works in a git repository")
+        })
+        .transpose()?;
+    let snapshot = rev
+        .map(|rev| {
+            Snapshot::open(root_path, rev)
+                .with_context(|| format!("Failed to read revision {}", rev))
+        })
+        .transpose()?;
+    // A revision only holds tracked files already
+    let tracked_files = if options.tracked_only && snapshot.is_none() {
+        let files = get_tracked_files(root_path, options.untracked)
+            .context("--tracked-only only works in a git repository")?;
+        Some(files)
+    } else {
+        None
+    };
+    let mut limited_files = Vec::new();
+
     let canonical_root_path = root_path.canonicalize()?;
     let parent_directory = label(&canonical_root_path);
 
+    let root_blob = match &snapshot {
+        Some(snapshot) => match snapshot.entry(&canonical_root_path)? {
+            Some(entry) => entry.blob,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "{} doesn't exist in revision {}",
+                    canonical_root_path.display(),
+                    rev.unwrap_or_default()
+                ))
+            }
+        },
+        None => None,
+    };
+    let source_of = |blob: Option<Oid>| match (&snapshot, blob) {
+        (Some(snapshot), Some(blob)) => FileSource::Blob(snapshot, blob),
+        _ => FileSource::Disk,
+    };
+
     // Handle single file case
-    if canonical_root_path.is_file() {
+    let root_is_file = match &snapshot {
+        Some(_) => root_blob.is_some(),
+        None => canonical_root_path.is_file(),
+    };
+    if root_is_file {
         let mut files = Vec::new();
-        if let Ok(code_bytes) = fs::read(&canonical_root_path) {
-            let mut code = String::from_utf8_lossy(&code_bytes).to_string();
-            code = code.replace(char::REPLACEMENT_CHARACTER, "[]");
-            // Always shorten base64 strings (regardless of extension)
-            code = shorten_long_base64_strings(&code);
-
+        let display_path = canonical_root_path.display().to_string();
+        let is_listed = options
+            .only_files
+            .as_ref()
+            .is_none_or(|files| files.contains(&canonical_root_path))
+            && tracked_files
+                .as_ref()
+                .is_none_or(|files| files.binary_search(&canonical_root_path).is_ok());
+        if !is_listed {
+            return Ok((display_path, files, limited_files));
+        }
+        let loaded = load_file(
+            &canonical_root_path,
+            source_of(root_blob),
+            options,
+            grep.as_ref(),
+            input_encoding,
+            None,
+            blamer.as_ref(),
+        );
+        if let Some(limited) = limited_file(&display_path, &loaded, options) {
+            limited_files.push(limited);
+        }
+        if let LoadedFile::Kept {
+            code,
+            numbered,
+            encoding,
+            ..
+        } = loaded
+        {
             let extension = canonical_root_path
                 .extension()
                 .and_then(|ext| ext.to_str())
                 .unwrap_or("");
-            let code_block = wrap_code_block(&code, extension, line_number, no_codeblock);
+            let code_block =
+                wrap_code_block(&code, extension, line_number && !numbered, no_codeblock);
 
             if !code.trim().is_empty() {
-                files.push(json!({
-                    "path": canonical_root_path.display().to_string(),
+                let mut file_entry = json!({
+                    "path": display_path,
                     "extension": extension,
+                    "tokens": tokenizer.count(&code_block)?,
                     "code": code_block,
-                }));
+                });
+                if let Some(encoding) = encoding {
+                    file_entry["encoding"] = json!(encoding);
+                }
+                if let Some(file_history) = &file_history {
+                    file_entry["history"] = history_json(file_history, &canonical_root_path);
+                }
+                files.push(file_entry);
             }
         }
-        return Ok((canonical_root_path.display().to_string(), files));
+        return Ok((display_path, files, limited_files));
     }
 
     // Respect a repo-local .c2pignore when present. If the repo hasn't opted
     // into that yet, fall back to the local .gitignore instead of walking
     // through every tracked build artifact in sight.
-    let has_root_c2pignore = canonical_root_path.join(".c2pignore").is_file();
-
-    // Directory case: Build WalkBuilder with ignore file support
-    let mut builder = WalkBuilder::new(&canonical_root_path);
-    builder
-        .hidden(false)
-        .ignore(true)
-        .git_ignore(!has_root_c2pignore)
-        .git_global(false)
-        .git_exclude(false)
-        .require_git(false)
-        .add_custom_ignore_filename(".c2pignore");
+    let root_c2pignore = canonical_root_path.join(".c2pignore");
+    let has_root_c2pignore = match &snapshot {
+        Some(snapshot) => snapshot
+            .entry(&root_c2pignore)?
+            .is_some_and(|entry| !entry.is_dir),
+        None => root_c2pignore.is_file(),
+    };
 
     // Create override builder for default excludes
     let mut override_builder = OverrideBuilder::new(&canonical_root_path);
@@ -116,6 +464,7 @@ pub fn traverse_directory(
     // 1) Add default excludes that will always apply
     let default_excludes = vec![
         // General "junk":
+        "!**/.git", // also the `.git` file of submodules and worktrees
         "!**/.git/**",
         "!**/.svn/**",
         "!**/.hg/**",
@@ -269,51 +618,59 @@ pub fn traverse_directory(
     }
 
     let overrides = override_builder.build()?;
-    builder.overrides(overrides);
-
-    let walker = builder.build();
 
     // If --include patterns are provided, compile them once for use inside the loop.
     let compiled_includes = compile_cli_patterns(include_patterns);
     let compiled_excludes = compile_cli_patterns(exclude_patterns);
+    let compiled_text = compile_cli_patterns(&options.text_patterns);
+    let compiled_binary = compile_cli_patterns(&options.binary_patterns);
 
     let mut root = Tree::new(parent_directory.clone());
     let mut collected_files = Vec::new();
+    let mut file_tokens: HashMap<PathBuf, usize> = HashMap::new();
 
-    // Define extensions we want in the tree but NOT in the context
-    let binary_extensions = vec![
-        "png", "jpg", "jpeg", "gif", "ico", "bmp", "tiff", "tif", "webp", "svg", "psd", "ai",
-        "xcf", "mp4", "mov", "avi", "mkv", "wmv", "flv", "webm", "m4v", "3gp", "mp3", "wav", "ogg",
-        "m4a", "flac", "aac", "wma", "mid", "midi", "pdf", "zip", "rar", "7z", "tar", "gz", "bz2",
-        "xz", "doc", "docx", "ppt", "pptx", "xls", "xlsx",
-    ];
+    // 2) Collect entries up front, so sampling can see every file in a directory
+    let entries = match (&snapshot, &tracked_files) {
+        (Some(snapshot), _) => snapshot_entries(
+            snapshot,
+            &canonical_root_path,
+            !has_root_c2pignore,
+            &overrides,
+        )?,
+        (None, Some(files)) => tracked_entries(&canonical_root_path, files),
+        (None, None) => {
+            // Directory case: Build WalkBuilder with ignore file support
+            let mut builder = WalkBuilder::new(&canonical_root_path);
+            builder
+                .hidden(false)
+                .ignore(true)
+                .git_ignore(!has_root_c2pignore)
+                .git_global(false)
+                .git_exclude(false)
+                .require_git(false)
+                .add_custom_ignore_filename(".c2pignore")
+                .overrides(overrides);
 
-    // 3) Traverse files
-    for result in walker {
-        let entry = match result {
-            Ok(e) => e,
-            Err(err) => {
-                debug!("Skipping entry due to error: {:?}", err);
-                continue;
+            let mut entries = Vec::new();
+            for result in builder.build() {
+                match result {
+                    Ok(entry) => entries.push(Entry {
+                        is_dir: entry.file_type().is_some_and(|ft| ft.is_dir()),
+                        path: entry.into_path(),
+                        blob: None,
+                    }),
+                    Err(err) => debug!("Skipping entry due to error: {:?}", err),
+                }
             }
-        };
-
-        let path = entry.path();
-        let relative = match path.strip_prefix(&canonical_root_path) {
-            Ok(r) => r,
-            Err(_) => path,
-        };
-
-        // Get extension early and check if binary
-        let extension = path
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-        let is_binary = binary_extensions.contains(&extension.as_str());
+            entries
+        }
+    };
 
-        // Check if path matches an --include pattern
-        let rel_str = relative.to_str().unwrap_or("");
+    let is_selected = |rel_str: &str| -> (bool, bool) {
+        let file_is_listed = options
+            .only_files
+            .as_ref()
+            .is_none_or(|files| files.contains(&canonical_root_path.join(rel_str)));
         let file_matches_include = compiled_includes
             .as_ref()
             .map(|patterns| patterns.iter().any(|p| p.matches(rel_str)))
@@ -333,6 +690,66 @@ pub fn traverse_directory(
             (false, _, true) => false,
             (false, _, false) => true,
         };
+        (file_selected && file_is_listed, file_matches_exclude)
+    };
+
+    let sampled_out = match sample_rate {
+        Some(rate) if rate > 1 => {
+            let selected_files: Vec<PathBuf> = entries
+                .iter()
+                .filter(|entry| !entry.is_dir)
+                .filter_map(|entry| entry.path.strip_prefix(&canonical_root_path).ok())
+                .filter(|relative| is_selected(relative.to_str().unwrap_or("")).0)
+                .map(Path::to_path_buf)
+                .collect();
+            sample_out_files(&selected_files, rate)
+        }
+        _ => HashSet::new(),
+    };
+
+    // 3) Traverse files
+    for entry in &entries {
+        let path = entry.path.as_path();
+        let relative = match path.strip_prefix(&canonical_root_path) {
+            Ok(r) => r,
+            Err(_) => path,
+        };
+
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        // Check if path matches an --include pattern
+        let rel_str = relative.to_str().unwrap_or("");
+        let (file_selected, file_matches_exclude) = is_selected(rel_str);
+        let is_sampled_out = sampled_out.contains(relative);
+        let is_dir = entry.is_dir;
+
+        // Load included files up front, so content filters can deselect them
+        let loaded = if file_selected && !is_dir && !is_sampled_out {
+            let binary_override = if matches_any(&compiled_text, rel_str) {
+                Some(false)
+            } else if matches_any(&compiled_binary, rel_str) {
+                Some(true)
+            } else {
+                None
+            };
+            load_file(
+                path,
+                source_of(entry.blob),
+                options,
+                grep.as_ref(),
+                input_encoding,
+                binary_override,
+                blamer.as_ref(),
+            )
+        } else {
+            LoadedFile::Unreadable
+        };
+        let file_selected = file_selected && !matches!(loaded, LoadedFile::Rejected);
+        let limited = limited_file(&relative.display().to_string(), &loaded, options);
 
         // Determine the "depth" by component count
         let depth = relative.components().count();
@@ -340,27 +757,39 @@ pub fn traverse_directory(
         // 1) Add item (file or directory) to the tree if:
         //    - It's included, OR
         //    - The depth is <= 3
-        if !exclude_from_tree {
-            if file_selected || (!file_matches_exclude && depth <= 3) {
-                add_path_to_tree(&mut root, relative);
+        if !exclude_from_tree && (file_selected || (!file_matches_exclude && depth <= 3)) {
+            let leaf = add_path_to_tree(&mut root, relative);
+            if is_sampled_out {
+                leaf.root.push_str(SAMPLED_OUT_MARKER);
+            }
+            if let Some(LimitedFile::Skipped { size, .. }) = &limited {
+                leaf.root
+                    .push_str(&format!(" [skipped: {}]", format_size(*size)));
+            }
+            if let LoadedFile::Binary(kind, size) = loaded {
+                leaf.root
+                    .push_str(&format!(" [binary: {}, {}]", kind, format_size(size)));
             }
         }
+        limited_files.extend(limited);
 
         // 2) If it's a directory, don't read its contents into "collected_files"
         //    We only do that for actual files below:
-        if entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
+        if is_dir {
             continue;
         }
 
-        // 3) If it's a file that is actually included AND not binary, read its content
-        if file_selected && !is_binary {
-            if let Ok(code_bytes) = fs::read(path) {
-                let mut code = String::from_utf8_lossy(&code_bytes).to_string();
-                code = code.replace(char::REPLACEMENT_CHARACTER, "[]");
-                // Always shorten base64 strings (regardless of extension)
-                code = shorten_long_base64_strings(&code);
-
-                let code_block = wrap_code_block(&code, &extension, line_number, no_codeblock);
+        // 3) If it's a file that is actually included AND not binary, add its content
+        if file_selected {
+            if let LoadedFile::Kept {
+                code,
+                numbered,
+                encoding,
+                ..
+            } = loaded
+            {
+                let code_block =
+                    wrap_code_block(&code, &extension, line_number && !numbered, no_codeblock);
 
                 if !code.trim().is_empty() {
                     let file_path = if relative_paths {
@@ -369,23 +798,425 @@ pub fn traverse_directory(
                         path.display().to_string()
                     };
 
-                    collected_files.push(json!({
+                    let tokens = tokenizer.count(&code_block)?;
+                    file_tokens.insert(relative.to_path_buf(), tokens);
+
+                    let mut file_entry = json!({
                         "path": file_path,
                         "extension": extension,
+                        "tokens": tokens,
                         "code": code_block,
-                    }));
+                    });
+                    if let Some(encoding) = encoding {
+                        file_entry["encoding"] = json!(encoding);
+                    }
+                    if let Some(file_history) = &file_history {
+                        file_entry["history"] = history_json(file_history, path);
+                    }
+                    collected_files.push(file_entry);
                 }
             }
         }
     }
 
+    if tree_tokens {
+        annotate_tree_tokens(&mut root, Path::new(""), &file_tokens);
+    }
+
     let tree_str = if exclude_from_tree {
         String::new()
     } else {
         root.to_string()
     };
 
-    Ok((tree_str, collected_files))
+    Ok((tree_str, collected_files, limited_files))
+}
+
+/// Reads a selected file, from disk or from a git revision, then applies the grep filter
+/// and the file size limits.
+///
+/// Only the first `SNIFF_LEN` bytes are read from disk until the file is known not to be binary.
+/// Text in other encodings is converted to UTF-8, see `decode_text`.
+/// `binary_override` forces the file to be read as text (`false`) or treated as binary (`true`).
+fn load_file(
+    path: &Path,
+    source: FileSource,
+    options: &TraverseOptions,
+    grep: Option<&GrepFilter>,
+    input_encoding: Option<&'static Encoding>,
+    binary_override: Option<bool>,
+    blamer: Option<&Blamer>,
+) -> LoadedFile {
+    let too_large = |size: u64| {
+        options
+            .max_file_size
+            .is_some_and(|max_file_size| size > max_file_size)
+    };
+    let is_binary =
+        |sample: &[u8]| binary_override.unwrap_or_else(|| is_binary_as(sample, input_encoding));
+
+    let bytes = match source {
+        FileSource::Disk => {
+            let Ok(mut file) = File::open(path) else {
+                return LoadedFile::Unreadable;
+            };
+            let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+            if too_large(size) {
+                return LoadedFile::TooLarge(size);
+            }
+
+            let mut bytes = Vec::new();
+            if (&mut file)
+                .take(SNIFF_LEN as u64)
+                .read_to_end(&mut bytes)
+                .is_err()
+            {
+                return LoadedFile::Unreadable;
+            }
+            if is_binary(&bytes) {
+                return LoadedFile::Binary(binary_type(&bytes), size);
+            }
+            if file.read_to_end(&mut bytes).is_err() {
+                return LoadedFile::Unreadable;
+            }
+            bytes
+        }
+        FileSource::Blob(snapshot, blob) => {
+            let Ok(bytes) = snapshot.read(blob) else {
+                return LoadedFile::Unreadable;
+            };
+            let size = bytes.len() as u64;
+            if too_large(size) {
+                return LoadedFile::TooLarge(size);
+            }
+            let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+            if is_binary(sample) {
+                return LoadedFile::Binary(binary_type(sample), size);
+            }
+            bytes
+        }
+    };
+    let (code, encoding) = decode_text(&bytes, input_encoding);
+    let code = clean_code(&code);
+    // Wide encodings don't have their lines at the same offsets as the blamed blob
+    let blame = blamer
+        .filter(|_| !encoding.is_some_and(|name| name.starts_with("UTF-16")))
+        .and_then(|blamer| match blamer.blame(path, &bytes) {
+            Ok(blame) => Some(blame),
+            Err(err) => {
+                print_warning(&format!(
+                    "Showing {} without blame: {:#}",
+                    path.display(),
+                    err
+                ));
+                None
+            }
+        });
+    let annotator = LineAnnotator::new(options.line_number, blame);
+    let (code, numbered) = match grep {
+        Some(grep) => match grep.apply(&code, &annotator) {
+            Some(kept) => kept,
+            None => return LoadedFile::Rejected,
+        },
+        None => (code, false),
+    };
+
+    // Matching regions are already short, so only whole files are truncated
+    if let Some(max_file_lines) = options.max_file_lines.filter(|_| !numbered) {
+        let total_lines = code.lines().count();
+        if total_lines > max_file_lines {
+            return LoadedFile::Kept {
+                code: truncate_lines(&code, max_file_lines, &annotator),
+                numbered: true,
+                truncated_from: Some(total_lines),
+                encoding,
+            };
+        }
+    }
+    // Line numbers alone are added when wrapping the code block
+    if annotator.blame.is_some() && !numbered {
+        return LoadedFile::Kept {
+            code: annotator.annotate_all(&code),
+            numbered: true,
+            truncated_from: None,
+            encoding,
+        };
+    }
+    LoadedFile::Kept {
+        code,
+        numbered,
+        truncated_from: None,
+        encoding,
+    }
+}
+
+/// Names of the ignore files honoured in a git revision, highest precedence first, as
+/// the walker does on disk.
+const IGNORE_FILE_NAMES: [&str; 3] = [".c2pignore", ".ignore", ".gitignore"];
+
+/// Lists the files and directories of a git revision below the root, leaving out the ones
+/// matched by the default excludes or by the ignore files of the revision.
+///
+/// Like on disk, ignore files apply to their own directory and everything below it, and the
+/// ignore files of the root's parent directories in the repository apply too. A deeper ignore
+/// file takes precedence over a shallower one of the same kind.
+fn snapshot_entries(
+    snapshot: &Snapshot,
+    root: &Path,
+    use_gitignore: bool,
+    overrides: &Override,
+) -> Result<Vec<Entry>> {
+    let walked = snapshot.walk(root)?;
+    let ignore_kinds: Vec<&str> = IGNORE_FILE_NAMES
+        .into_iter()
+        .filter(|name| use_gitignore || *name != ".gitignore")
+        .collect();
+    let build_ignore = |dir: &Path, blob: Oid| -> Result<Gitignore> {
+        let contents = snapshot.read(blob)?;
+        let mut builder = GitignoreBuilder::new(dir);
+        for line in String::from_utf8_lossy(&contents).lines() {
+            if let Err(err) = builder.add_line(None, line) {
+                debug!(
+                    "Skipping invalid ignore pattern in {}: {}",
+                    dir.display(),
+                    err
+                );
+            }
+        }
+        Ok(builder.build()?)
+    };
+
+    // The ignore files of each kind, by directory
+    let mut ignores: HashMap<(usize, PathBuf), Gitignore> = HashMap::new();
+    for dir in root.ancestors().skip(1) {
+        for (kind, name) in ignore_kinds.iter().enumerate() {
+            // Stop at the top of the repository
+            let Ok(entry) = snapshot.entry(&dir.join(name)) else {
+                break;
+            };
+            if let Some(SnapshotEntry {
+                blob: Some(blob), ..
+            }) = entry
+            {
+                ignores.insert((kind, dir.to_path_buf()), build_ignore(dir, blob)?);
+            }
+        }
+    }
+    for entry in &walked {
+        let Some(blob) = entry.blob else {
+            continue;
+        };
+        let name = entry.path.file_name().and_then(|name| name.to_str());
+        if let Some(kind) = ignore_kinds.iter().position(|kind| Some(*kind) == name) {
+            let dir = root.join(entry.path.parent().unwrap_or(Path::new("")));
+            ignores.insert((kind, dir.clone()), build_ignore(&dir, blob)?);
+        }
+    }
+
+    let is_ignored = |path: &Path, is_dir: bool| -> bool {
+        match overrides.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+        for kind in 0..ignore_kinds.len() {
+            for dir in path.ancestors().skip(1) {
+                let Some(ignore) = ignores.get(&(kind, dir.to_path_buf())) else {
+                    continue;
+                };
+                match ignore.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        false
+    };
+
+    // Directories come before their contents, so ignored directories are known in time
+    let mut ignored_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut entries = Vec::new();
+    for entry in walked {
+        let path = root.join(&entry.path);
+        let in_ignored_dir = path
+            .parent()
+            .is_some_and(|parent| ignored_dirs.contains(parent));
+        if in_ignored_dir || is_ignored(&path, entry.is_dir) {
+            if entry.is_dir {
+                ignored_dirs.insert(path);
+            }
+            continue;
+        }
+        entries.push(Entry {
+            path,
+            is_dir: entry.is_dir,
+            blob: entry.blob,
+        });
+    }
+    Ok(entries)
+}
+
+/// Lists the tracked files under the root, each preceded by the directories leading to it.
+///
+/// Git decides what is part of the project, so neither the default excludes nor the
+/// ignore files apply. `files` must be sorted, so directories come before their contents.
+fn tracked_entries(root: &Path, files: &[PathBuf]) -> Vec<Entry> {
+    let mut seen_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut entries = Vec::new();
+    for file in files {
+        let Ok(relative) = file.strip_prefix(root) else {
+            continue;
+        };
+        let mut dir = root.to_path_buf();
+        if let Some(parent) = relative.parent() {
+            for component in parent.components() {
+                dir.push(component);
+                if seen_dirs.insert(dir.clone()) {
+                    entries.push(Entry {
+                        path: dir.clone(),
+                        is_dir: true,
+                        blob: None,
+                    });
+                }
+            }
+        }
+        entries.push(Entry {
+            path: file.clone(),
+            is_dir: false,
+            blob: None,
+        });
+    }
+    entries
+}
+
+/// Returns the recent commits of a file as JSON, or an empty list if it isn't tracked or
+/// its history can't be read.
+fn history_json(file_history: &FileHistory, path: &Path) -> serde_json::Value {
+    let entries = file_history.for_file(path).unwrap_or_else(|err| {
+        print_warning(&format!(
+            "Showing {} without history: {:#}",
+            path.display(),
+            err
+        ));
+        Vec::new()
+    });
+    serde_json::Value::Array(entries.iter().map(|entry| entry.to_json()).collect())
+}
+
+/// Returns the summary entry for a file that hit one of the file size limits.
+fn limited_file(path: &str, loaded: &LoadedFile, options: &TraverseOptions) -> Option<LimitedFile> {
+    match *loaded {
+        LoadedFile::TooLarge(size) => Some(LimitedFile::Skipped {
+            path: path.to_string(),
+            size,
+        }),
+        LoadedFile::Kept {
+            truncated_from: Some(total_lines),
+            ..
+        } => Some(LimitedFile::Truncated {
+            path: path.to_string(),
+            total_lines,
+            kept_lines: options.max_file_lines.unwrap_or(total_lines),
+        }),
+        _ => None,
+    }
+}
+
+/// Keeps the first and last lines of the code, replacing the lines in between with a marker.
+fn truncate_lines(code: &str, max_lines: usize, annotator: &LineAnnotator) -> String {
+    let lines: Vec<&str> = code.lines().collect();
+    let head = max_lines.div_ceil(2);
+    let tail_start = lines.len() - max_lines / 2;
+    let keep: Vec<bool> = (0..lines.len())
+        .map(|index| index < head || index >= tail_start)
+        .collect();
+    join_kept_lines(&lines, &keep, annotator, |truncated| {
+        format!("... [truncated {} lines] ...", truncated)
+    })
+}
+
+/// Joins the kept lines of a file, annotating them with their original line numbers or
+/// blame if requested and replacing each run of dropped lines with a marker line.
+fn join_kept_lines(
+    lines: &[&str],
+    keep: &[bool],
+    annotator: &LineAnnotator,
+    marker: impl Fn(usize) -> String,
+) -> String {
+    let mut joined = String::new();
+    let mut dropped = 0;
+    for (index, line) in lines.iter().enumerate() {
+        if !keep[index] {
+            dropped += 1;
+            continue;
+        }
+        if dropped > 0 {
+            joined.push_str(&marker(dropped));
+            joined.push('\n');
+            dropped = 0;
+        }
+        if annotator.is_active() {
+            joined.push_str(&annotator.annotate(index, line));
+        } else {
+            joined.push_str(line);
+            joined.push('\n');
+        }
+    }
+    if dropped > 0 {
+        joined.push_str(&marker(dropped));
+        joined.push('\n');
+    }
+    joined
+}
+
+/// Formats a size in bytes for humans, e.g. `40.2 MB`.
+///
+/// # Arguments
+///
+/// * `bytes` - The size in bytes.
+///
+/// # Returns
+///
+/// * `String` - The formatted size.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Prints a warning about a git operation that failed for a single file, which is still
+/// included without that information.
+fn print_warning(message: &str) {
+    eprintln!(
+        "{}{}{} {}",
+        "[".bold().white(),
+        "!".bold().yellow(),
+        "]".bold().white(),
+        message.yellow()
+    );
+}
+
+/// Cleans up decoded file contents, marking undecodable bytes and shortening long base64 strings.
+fn clean_code(code: &str) -> String {
+    let code = code.replace(char::REPLACEMENT_CHARACTER, "[]");
+    // Always shorten base64 strings (regardless of extension)
+    shorten_long_base64_strings(&code)
+}
+
+/// Checks whether a path matches any of the compiled patterns.
+fn matches_any(patterns: &Option<Vec<Pattern>>, rel_str: &str) -> bool {
+    patterns
+        .as_ref()
+        .is_some_and(|patterns| patterns.iter().any(|p| p.matches(rel_str)))
 }
 
 fn compile_cli_patterns(patterns: &[String]) -> Option<Vec<Pattern>> {
@@ -412,8 +1243,62 @@ fn compile_cli_patterns(patterns: &[String]) -> Option<Vec<Pattern>> {
     )
 }
 
-/// Helper to nest a relative path in the tree structure
-fn add_path_to_tree(root: &mut Tree<String>, rel_path: &Path) {
+/// Appends token counts to the labels of files and directories in the tree.
+///
+/// Returns the total number of tokens below `node`.
+fn annotate_tree_tokens(
+    node: &mut Tree<String>,
+    node_path: &Path,
+    file_tokens: &HashMap<PathBuf, usize>,
+) -> usize {
+    let total = if node.leaves.is_empty() {
+        file_tokens.get(node_path).copied().unwrap_or(0)
+    } else {
+        let mut total = 0;
+        for leaf in node.leaves.iter_mut() {
+            let leaf_path = node_path.join(&leaf.root);
+            total += annotate_tree_tokens(leaf, &leaf_path, file_tokens);
+        }
+        total
+    };
+
+    if total > 0 {
+        node.root.push_str(&format!(" [{} tokens]", total));
+    }
+    total
+}
+
+/// Picks the files to leave out when keeping 1 in `rate` files per directory.
+///
+/// Every directory keeps at least one file. The choice is seeded by the directory
+/// path, so repeated runs over the same tree produce the same sample.
+fn sample_out_files(files: &[PathBuf], rate: u8) -> HashSet<PathBuf> {
+    let mut by_directory: BTreeMap<&Path, Vec<&PathBuf>> = BTreeMap::new();
+    for file in files {
+        let directory = file.parent().unwrap_or_else(|| Path::new(""));
+        by_directory.entry(directory).or_default().push(file);
+    }
+
+    let mut sampled_out = HashSet::new();
+    for (directory, mut group) in by_directory {
+        group.sort();
+        let keep = group.len().div_ceil(rate as usize);
+        let seed = SAMPLE_SEED ^ fnv1a(directory.to_string_lossy().as_bytes());
+        group.shuffle(&mut StdRng::seed_from_u64(seed));
+        sampled_out.extend(group[keep..].iter().map(|file| file.to_path_buf()));
+    }
+    sampled_out
+}
+
+/// FNV-1a hash, used instead of `DefaultHasher` because it's stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Helper to nest a relative path in the tree structure, returning the leaf node
+fn add_path_to_tree<'a>(root: &'a mut Tree<String>, rel_path: &Path) -> &'a mut Tree<String> {
     use std::path::Component;
     let mut current = root;
     for c in rel_path.components() {
@@ -429,6 +1314,7 @@ fn add_path_to_tree(root: &mut Tree<String>, rel_path: &Path) {
             }
         }
     }
+    current
 }
 
 /// Returns the file name or the string representation of the path.
@@ -10,6 +10,265 @@ use regex::Regex;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+/// Name of the template used when no `--template` is given.
+pub const DEFAULT_TEMPLATE_NAME: &str = "default";
+
+/// Fields of the items of the arrays in the template data, by array name. They are only
+/// defined inside an `{{#each}}` block over such an array.
+const ITEM_FIELDS: &[(&str, &[&str])] = &[
+    (
+        "files",
+        &[
+            "path",
+            "extension",
+            "code",
+            "tokens",
+            "encoding",
+            "history",
+            // The files of a commit
+            "insertions",
+            "deletions",
+        ],
+    ),
+    (
+        "commits",
+        &[
+            "sha",
+            "short_sha",
+            "author",
+            "email",
+            "date",
+            "summary",
+            "body",
+            "files",
+            "files_changed",
+            "insertions",
+            "deletions",
+        ],
+    ),
+    ("history", &["sha", "short_sha", "date", "summary"]),
+    ("diff_files", DIFF_FILE_FIELDS),
+    ("diff_files_branch", DIFF_FILE_FIELDS),
+];
+
+/// Fields of the files of a git diff.
+const DIFF_FILE_FIELDS: &[&str] = &[
+    "path",
+    "old_path",
+    "change_type",
+    "additions",
+    "deletions",
+    "binary",
+    "hunks",
+];
+
+/// The templates embedded in the binary, by name.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    (DEFAULT_TEMPLATE_NAME, include_str!("default_template.hbs")),
+    (
+        "binary-exploitation-ctf-solver",
+        include_str!("../templates/binary-exploitation-ctf-solver.hbs"),
+    ),
+    ("claude-xml", include_str!("../templates/claude-xml.hbs")),
+    (
+        "clean-up-code",
+        include_str!("../templates/clean-up-code.hbs"),
+    ),
+    (
+        "cryptography-ctf-solver",
+        include_str!("../templates/cryptography-ctf-solver.hbs"),
+    ),
+    (
+        "document-the-code",
+        include_str!("../templates/document-the-code.hbs"),
+    ),
+    ("efficient", include_str!("../templates/efficient.hbs")),
+    (
+        "efficient-minimal",
+        include_str!("../templates/efficient-minimal.hbs"),
+    ),
+    (
+        "efficient-with-tree",
+        include_str!("../templates/efficient-with-tree.hbs"),
+    ),
+    (
+        "find-security-vulnerabilities",
+        include_str!("../templates/find-security-vulnerabilities.hbs"),
+    ),
+    ("fix-bugs", include_str!("../templates/fix-bugs.hbs")),
+    (
+        "improve-performance",
+        include_str!("../templates/improve-performance.hbs"),
+    ),
+    ("refactor", include_str!("../templates/refactor.hbs")),
+    (
+        "reverse-engineering-ctf-solver",
+        include_str!("../templates/reverse-engineering-ctf-solver.hbs"),
+    ),
+    (
+        "ultra-efficient",
+        include_str!("../templates/ultra-efficient.hbs"),
+    ),
+    (
+        "web-ctf-solver",
+        include_str!("../templates/web-ctf-solver.hbs"),
+    ),
+    (
+        "write-git-commit",
+        include_str!("../templates/write-git-commit.hbs"),
+    ),
+    (
+        "write-github-pull-request",
+        include_str!("../templates/write-github-pull-request.hbs"),
+    ),
+    (
+        "write-github-readme",
+        include_str!("../templates/write-github-readme.hbs"),
+    ),
+];
+
+/// Where a template was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateSource {
+    /// Embedded in the binary.
+    Builtin,
+    /// A `.hbs` file in the user template directory.
+    User(PathBuf),
+    /// A template file given by its path.
+    File(PathBuf),
+}
+
+/// A Handlebars template, resolved by name or path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    /// The name of the template, or the file stem of a template given by its path.
+    pub name: String,
+    /// The Handlebars source of the template.
+    pub content: String,
+    /// Where the template was found.
+    pub source: TemplateSource,
+}
+
+impl Template {
+    /// Returns the one-line description of the template, taken from a `{{!-- ... --}}`
+    /// or `{{! ... }}` comment on its first line.
+    pub fn description(&self) -> Option<&str> {
+        let first_line = self.content.lines().next()?.trim();
+        let comment = first_line
+            .strip_prefix("{{!--")
+            .and_then(|rest| rest.strip_suffix("--}}"))
+            .or_else(|| {
+                first_line
+                    .strip_prefix("{{!")
+                    .and_then(|rest| rest.strip_suffix("}}"))
+            })?
+            .trim();
+        (!comment.is_empty()).then_some(comment)
+    }
+}
+
+/// Finds a template by name or path.
+///
+/// A name is looked up as `<name>.hbs` in the user template directory first, so users can
+/// override the built-in templates, then among the built-in templates. Anything else is read
+/// as the path of a template file.
+///
+/// # Arguments
+///
+/// * `name_or_path` - The name of a template (e.g. `fix-bugs`) or the path of a template file.
+/// * `user_dir` - The user template directory, if any.
+///
+/// # Returns
+///
+/// * `Result<Template>` - The template, or an error if it can't be found or read.
+pub fn find_template(name_or_path: &str, user_dir: Option<&Path>) -> Result<Template> {
+    let is_name = Path::new(name_or_path).components().count() == 1;
+    if let Some(user_dir) = user_dir.filter(|_| is_name) {
+        let path = user_dir.join(format!("{}.hbs", name_or_path));
+        if path.is_file() {
+            return read_template(name_or_path, TemplateSource::User(path));
+        }
+    }
+    if let Some((name, content)) = BUILTIN_TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == name_or_path)
+    {
+        return Ok(Template {
+            name: name.to_string(),
+            content: content.to_string(),
+            source: TemplateSource::Builtin,
+        });
+    }
+
+    let path = Path::new(name_or_path);
+    if !path.is_file() {
+        return Err(anyhow::anyhow!(
+            "No template named '{}' and no template file at that path (see --list-templates)",
+            name_or_path
+        ));
+    }
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name_or_path.to_string());
+    read_template(&name, TemplateSource::File(path.to_path_buf()))
+}
+
+/// Lists the built-in templates and the ones in the user template directory, sorted by name.
+///
+/// A user template with the same name as a built-in one replaces it.
+///
+/// # Arguments
+///
+/// * `user_dir` - The user template directory, if any.
+///
+/// # Returns
+///
+/// * `Result<Vec<Template>>` - The templates, or an error if a user template can't be read.
+pub fn list_templates(user_dir: Option<&Path>) -> Result<Vec<Template>> {
+    let mut templates: Vec<Template> = Vec::new();
+    let user_templates = user_dir
+        .and_then(|dir| std::fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "hbs"));
+    for path in user_templates {
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        templates.push(read_template(&name, TemplateSource::User(path))?);
+    }
+    for (name, content) in BUILTIN_TEMPLATES {
+        if !templates.iter().any(|template| template.name == *name) {
+            templates.push(Template {
+                name: name.to_string(),
+                content: content.to_string(),
+                source: TemplateSource::Builtin,
+            });
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(templates)
+}
+
+/// Reads a template file found by name or path.
+fn read_template(name: &str, source: TemplateSource) -> Result<Template> {
+    let (TemplateSource::User(path) | TemplateSource::File(path)) = &source else {
+        return Err(anyhow::anyhow!("Template {} isn't a file", name));
+    };
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read template file {}", path.display()))?;
+    Ok(Template {
+        name: name.to_string(),
+        content,
+        source,
+    })
+}
+
 /// Set up the Handlebars template engine with a template string and a template name.
 ///
 /// # Arguments
@@ -33,6 +292,9 @@ pub fn handlebars_setup(template_str: &str, template_name: &str) -> Result<Handl
 
 /// Extracts the undefined variables from the template string.
 ///
+/// The fields of array items, such as the `author` of a commit, only count as defined
+/// inside an `{{#each}}` block over their array.
+///
 /// # Arguments
 ///
 /// * `template` - The Handlebars template string.
@@ -41,12 +303,48 @@ pub fn handlebars_setup(template_str: &str, template_name: &str) -> Result<Handl
 ///
 /// * `Vec<String>` - A vector of undefined variable names.
 pub fn extract_undefined_variables(template: &str) -> Vec<String> {
-    let registered_identifiers = ["path", "code", "git_diff"];
-    let re = Regex::new(r"\{\{\s*(?P<var>[a-zA-Z_][a-zA-Z_0-9]*)\s*\}\}").unwrap();
-    re.captures_iter(template)
-        .map(|cap| cap["var"].to_string())
-        .filter(|var| !registered_identifiers.contains(&var.as_str()))
-        .collect()
+    let registered_identifiers = [
+        "path", "code", "git_diff", // The `{{else}}` of a block
+        "else",
+    ];
+    let re = Regex::new(r"\{\{(?P<tag>[^}]*)\}\}").unwrap();
+    let identifier = Regex::new(r"^[a-zA-Z_][a-zA-Z_0-9]*$").unwrap();
+
+    // The array each enclosing block iterates over, `None` for other blocks
+    let mut blocks: Vec<Option<&str>> = Vec::new();
+    let mut variables = Vec::new();
+    for cap in re.captures_iter(template) {
+        let tag = cap
+            .name("tag")
+            .map_or("", |tag| tag.as_str())
+            .trim_matches(|c: char| c == '{' || c == '~' || c.is_whitespace());
+        if let Some(block) = tag.strip_prefix('#') {
+            let mut words = block.split_whitespace();
+            let array = match (words.next(), words.next()) {
+                (Some("each"), Some(array)) => array.rsplit(['.', '/']).next(),
+                _ => None,
+            };
+            blocks.push(array);
+        } else if tag.starts_with('/') {
+            blocks.pop();
+        } else if identifier.is_match(tag)
+            && !registered_identifiers.contains(&tag)
+            && !is_item_field(&blocks, tag)
+        {
+            variables.push(tag.to_string());
+        }
+    }
+    variables
+}
+
+/// Returns whether `var` is a field of the items of the innermost `{{#each}}` block.
+fn is_item_field(blocks: &[Option<&str>], var: &str) -> bool {
+    let Some(array) = blocks.iter().rev().flatten().next() else {
+        return false;
+    };
+    ITEM_FIELDS
+        .iter()
+        .any(|(name, fields)| name == array && fields.contains(&var))
 }
 
 /// Renders the template with the provided data.
@@ -71,12 +369,80 @@ pub fn render_template(
     Ok(rendered.trim().to_string())
 }
 
+/// Collects the values of user-defined variables from `--var-file` files and `--var`
+/// arguments, the latter overriding the former.
+///
+/// A `--var` is `KEY=VALUE`, or `KEY=@PATH` to use the contents of a file as the value.
+/// Variable files hold a JSON or TOML object, depending on their extension.
+///
+/// # Arguments
+///
+/// * `vars` - The `KEY=VALUE` or `KEY=@PATH` arguments.
+/// * `var_files` - The paths of the `.json` or `.toml` variable files, lowest precedence first.
+///
+/// # Returns
+///
+/// * `Result<serde_json::Map<String, serde_json::Value>>` - The variables, by name.
+pub fn load_template_variables(
+    vars: &[String],
+    var_files: &[PathBuf],
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let mut variables = serde_json::Map::new();
+    for path in var_files {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read variable file {}", path.display()))?;
+        let value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse variable file {}", path.display()))?,
+            Some("toml") => {
+                let table: toml::Table = contents
+                    .parse()
+                    .with_context(|| format!("Failed to parse variable file {}", path.display()))?;
+                serde_json::to_value(table)?
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported variable file {} (expected a .json or .toml file)",
+                    path.display()
+                ))
+            }
+        };
+        match value {
+            serde_json::Value::Object(object) => variables.extend(object),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Variable file {} must hold an object of variables",
+                    path.display()
+                ))
+            }
+        }
+    }
+
+    for var in vars {
+        let (key, value) = var
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Invalid variable '{}' (expected KEY=VALUE)", var))?;
+        let value = match value.strip_prefix('@') {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the value of {} from {}", key, path))?,
+            None => value.to_string(),
+        };
+        variables.insert(key.trim().to_string(), serde_json::Value::String(value));
+    }
+    Ok(variables)
+}
+
 /// Handles user-defined variables in the template and adds them to the data.
 ///
+/// Variables that are still missing from the data are prompted for, or reported as an error
+/// if prompting isn't allowed.
+///
 /// # Arguments
 ///
 /// * `data` - The JSON data object.
 /// * `template_content` - The template content string.
+/// * `interactive` - Whether to prompt for the missing variables.
 ///
 /// # Returns
 ///
@@ -84,19 +450,28 @@ pub fn render_template(
 pub fn handle_undefined_variables(
     data: &mut serde_json::Value,
     template_content: &str,
+    interactive: bool,
 ) -> Result<()> {
-    let undefined_variables = extract_undefined_variables(template_content);
-    let mut user_defined_vars = serde_json::Map::new();
+    let mut missing_variables = extract_undefined_variables(template_content);
+    missing_variables.retain(|var| !data.as_object().unwrap().contains_key(var));
+    let mut seen = std::collections::HashSet::new();
+    missing_variables.retain(|var| seen.insert(var.clone()));
 
-    for var in undefined_variables.iter() {
-        if !data.as_object().unwrap().contains_key(var) {
-            let prompt = format!("Enter value for '{}': ", var);
-            let answer = Text::new(&prompt)
-                .with_help_message("Fill user defined variable in template")
-                .prompt()
-                .unwrap_or_default();
-            user_defined_vars.insert(var.clone(), serde_json::Value::String(answer));
-        }
+    if !interactive && !missing_variables.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Missing values for template variables: {} (set them with --var or --var-file)",
+            missing_variables.join(", ")
+        ));
+    }
+
+    let mut user_defined_vars = serde_json::Map::new();
+    for var in missing_variables {
+        let prompt = format!("Enter value for '{}': ", var);
+        let answer = Text::new(&prompt)
+            .with_help_message("Fill user defined variable in template")
+            .prompt()
+            .unwrap_or_default();
+        user_defined_vars.insert(var, serde_json::Value::String(answer));
     }
 
     if let Some(obj) = data.as_object_mut() {
@@ -305,7 +680,11 @@ fn copy_file_to_clipboard_windows(path: &Path) -> Result<()> {
     Ok(())
 }
 
-#[cfg(all(not(target_os = "macos"), not(target_os = "windows")))]
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "windows"),
+    not(target_os = "linux")
+))]
 fn copy_file_to_clipboard_fallback(path: &Path) -> Result<()> {
     let contents = std::fs::read_to_string(path)
         .with_context(|| format!("Failed to read context file: {}", path.display()))?;
@@ -327,7 +706,7 @@ pub fn write_to_file(output_path: &str, rendered: &str) -> Result<()> {
     let file = std::fs::File::create(output_path)?;
     let mut writer = std::io::BufWriter::new(file);
     write!(writer, "{}", rendered)?;
-    println!(
+    eprintln!(
         "{}{}{} {}",
         "[".bold().white(),
         "✓".bold().green(),
@@ -1,26 +1,137 @@
 //! This module encapsulates the logic for counting the tokens in the rendered text.
 
+use anyhow::{anyhow, Result};
 use colored::*;
-use tiktoken_rs::{cl100k_base, p50k_base, p50k_edit, r50k_base, CoreBPE};
+use std::path::Path;
+use tiktoken_rs::{cl100k_base, o200k_base, p50k_base, p50k_edit, r50k_base, CoreBPE};
+use tokenizers::models::bpe::BPE;
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+
+/// The encodings accepted by `--encoding`.
+const SUPPORTED_ENCODINGS: &str = "o200k, cl100k, p50k, p50k_edit, r50k, gpt2";
+
+/// Known model families: (model name prefix, encoding, context window in tokens).
+///
+/// Anthropic doesn't publish the Claude tokenizer, so Claude models are
+/// approximated with cl100k.
+const MODELS: &[(&str, &str, usize)] = &[
+    ("gpt-4o", "o200k", 128_000),
+    ("gpt-4.1", "o200k", 1_047_576),
+    ("gpt-4.5", "o200k", 128_000),
+    ("gpt-5", "o200k", 400_000),
+    ("o1", "o200k", 200_000),
+    ("o3", "o200k", 200_000),
+    ("o4", "o200k", 200_000),
+    ("gpt-4-turbo", "cl100k", 128_000),
+    ("gpt-4-32k", "cl100k", 32_768),
+    ("gpt-4", "cl100k", 8_192),
+    ("gpt-3.5-turbo", "cl100k", 16_385),
+    ("text-embedding", "cl100k", 8_191),
+    ("claude", "cl100k", 200_000),
+    ("text-davinci-edit", "p50k_edit", 4_097),
+    ("code-davinci-edit", "p50k_edit", 4_097),
+    ("text-davinci", "p50k", 4_097),
+    ("code-davinci", "p50k", 8_001),
+    ("davinci", "r50k", 2_049),
+];
+
+/// A model selected with `--model`, along with its encoding and context window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelInfo {
+    /// The model name, as given on the command line.
+    pub name: String,
+    /// The encoding used to count tokens for this model.
+    pub encoding: &'static str,
+    /// The maximum number of tokens the model accepts.
+    pub context_window: usize,
+}
+
+/// A tokenizer used for token counting: either a built-in tiktoken encoding or a
+/// HuggingFace tokenizer loaded from local files.
+pub enum Tokenizer {
+    /// One of the OpenAI encodings bundled with `tiktoken-rs`.
+    Tiktoken(CoreBPE),
+    /// A tokenizer loaded from a `tokenizer.json` or a vocab/merges pair.
+    HuggingFace(Box<tokenizers::Tokenizer>),
+}
+
+impl Tokenizer {
+    /// Counts the tokens in the given text.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize>` - The number of tokens, or an error if a HuggingFace tokenizer
+    ///   fails to encode the text.
+    pub fn count(&self, text: &str) -> Result<usize> {
+        match self {
+            Tokenizer::Tiktoken(bpe) => Ok(bpe.encode_with_special_tokens(text).len()),
+            Tokenizer::HuggingFace(tokenizer) => tokenizer
+                .encode(text, false)
+                .map(|encoding| encoding.len())
+                .map_err(|e| anyhow!("Failed to count tokens: {}", e)),
+        }
+    }
+}
 
 /// Returns the appropriate tokenizer based on the provided encoding.
 ///
 /// # Arguments
 ///
 /// * `encoding` - An optional string specifying the encoding to use for tokenization.
-///                Supported encodings: "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2".
+///   Supported encodings: "o200k", "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2",
+///   a path to a HuggingFace `tokenizer.json`, or a comma-separated `vocab.json,merges.txt` pair.
 ///
 /// # Returns
 ///
-/// * `CoreBPE` - The tokenizer corresponding to the specified encoding.
-pub fn get_tokenizer(encoding: &Option<String>) -> CoreBPE {
-    match encoding.as_deref().unwrap_or("cl100k") {
-        "cl100k" => cl100k_base().unwrap(),
-        "p50k" => p50k_base().unwrap(),
-        "p50k_edit" => p50k_edit().unwrap(),
-        "r50k" | "gpt2" => r50k_base().unwrap(),
-        _ => cl100k_base().unwrap(),
+/// * `Result<Tokenizer>` - The tokenizer corresponding to the specified encoding, or an
+///   error if the encoding is unknown or the tokenizer files can't be loaded.
+pub fn get_tokenizer(encoding: &Option<String>) -> Result<Tokenizer> {
+    if let Some(files) = custom_tokenizer_files(encoding) {
+        return load_huggingface_tokenizer(&files).map(|t| Tokenizer::HuggingFace(Box::new(t)));
     }
+
+    let bpe = match normalize_encoding(encoding)? {
+        "o200k" => o200k_base(),
+        "cl100k" => cl100k_base(),
+        "p50k" => p50k_base(),
+        "p50k_edit" => p50k_edit(),
+        _ => r50k_base(),
+    };
+    bpe.map(Tokenizer::Tiktoken)
+        .map_err(|e| anyhow!("Failed to load tokenizer: {}", e))
+}
+
+/// Looks up a model by name (e.g. "gpt-4o" or "claude-3-5-sonnet").
+///
+/// The longest matching name prefix wins, so dated or suffixed model names
+/// resolve to their family.
+///
+/// # Arguments
+///
+/// * `model` - The model name.
+///
+/// # Returns
+///
+/// * `Result<ModelInfo>` - The model's encoding and context window, or an error if the model is unknown.
+pub fn get_model(model: &str) -> Result<ModelInfo> {
+    let lowercase = model.to_lowercase();
+    MODELS
+        .iter()
+        .filter(|(prefix, _, _)| lowercase.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|(_, encoding, context_window)| ModelInfo {
+            name: model.to_string(),
+            encoding,
+            context_window: *context_window,
+        })
+        .ok_or_else(|| {
+            let known: Vec<&str> = MODELS.iter().map(|(prefix, _, _)| *prefix).collect();
+            anyhow!(
+                "Unknown model '{}'. Known model families: {}",
+                model,
+                known.join(", ")
+            )
+        })
 }
 
 /// Returns the model information based on the provided encoding.
@@ -28,19 +139,41 @@ pub fn get_tokenizer(encoding: &Option<String>) -> CoreBPE {
 /// # Arguments
 ///
 /// * `encoding` - An optional string specifying the encoding to use for retrieving model information.
-///                Supported encodings: "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2".
+///   Supported encodings: "o200k", "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2".
+/// * `model` - The model selected with `--model`, if any.
+/// * `token_count` - The number of tokens in the rendered prompt.
 ///
 /// # Returns
 ///
-/// * `&'static str` - A string describing the models associated with the specified encoding.
-pub fn get_model_info(encoding: &Option<String>) -> &'static str {
-    match encoding.as_deref().unwrap_or("cl100k") {
+/// * `String` - How much of the model's context window is used, or a description of the
+///   models associated with the encoding when no model was selected.
+pub fn get_model_info(
+    encoding: &Option<String>,
+    model: Option<&ModelInfo>,
+    token_count: usize,
+) -> String {
+    if let Some(model) = model {
+        return format!(
+            "{}: {} of {} tokens used ({:.1}%)",
+            model.name,
+            token_count,
+            model.context_window,
+            token_count as f64 * 100.0 / model.context_window as f64
+        );
+    }
+
+    if let Some(files) = custom_tokenizer_files(encoding) {
+        return format!("Custom tokenizer ({})", files.join(", "));
+    }
+
+    match normalize_encoding(encoding).unwrap_or("cl100k") {
+        "o200k" => "GPT-4o models, o1, o3, o4-mini",
         "cl100k" => "ChatGPT models, text-embedding-ada-002",
         "p50k" => "Code models, text-davinci-002, text-davinci-003",
         "p50k_edit" => "Edit models like text-davinci-edit-001, code-davinci-edit-001",
-        "r50k" | "gpt2" => "GPT-3 models like davinci",
-        _ => "ChatGPT models, text-embedding-ada-002",
+        _ => "GPT-3 models like davinci",
     }
+    .to_string()
 }
 
 /// Counts the tokens in the rendered text using the specified encoding and prints the result.
@@ -49,30 +182,509 @@ pub fn get_model_info(encoding: &Option<String>) -> &'static str {
 ///
 /// * `rendered` - The rendered template string.
 /// * `encoding` - An optional string specifying the encoding to use for token counting.
-///                Supported encodings: "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2".
-pub fn count_tokens(rendered: &str, encoding: &Option<String>) {
-    let (bpe, model_info) = match encoding.as_deref().unwrap_or("cl100k") {
-        "cl100k" => (cl100k_base(), "ChatGPT models, text-embedding-ada-002"),
-        "p50k" => (
-            p50k_base(),
-            "Code models, text-davinci-002, text-davinci-003",
-        ),
-        "p50k_edit" => (
-            p50k_edit(),
-            "Edit models like text-davinci-edit-001, code-davinci-edit-001",
-        ),
-        "r50k" | "gpt2" => (r50k_base(), "GPT-3 models like davinci"),
-        _ => (cl100k_base(), "ChatGPT models, text-embedding-ada-002"),
-    };
-
-    let token_count = bpe.unwrap().encode_with_special_tokens(rendered).len();
+///   Supported encodings: "o200k", "cl100k" (default), "p50k", "p50k_edit", "r50k", "gpt2".
+///
+/// # Returns
+///
+/// * `Result<()>` - An empty result, or an error if the encoding is unknown.
+pub fn count_tokens(rendered: &str, encoding: &Option<String>) -> Result<()> {
+    let tokenizer = get_tokenizer(encoding)?;
+    let token_count = tokenizer.count(rendered)?;
 
-    println!(
+    eprintln!(
         "{}{}{} Token count: {}, Model info: {}",
         "[".bold().white(),
         "i".bold().blue(),
         "]".bold().white(),
         token_count.to_string().bold().yellow(),
-        model_info
+        get_model_info(encoding, None, token_count)
     );
+    Ok(())
+}
+
+/// Returns the local tokenizer file(s) named by `encoding`, if it refers to files
+/// rather than a built-in encoding.
+fn custom_tokenizer_files(encoding: &Option<String>) -> Option<Vec<&str>> {
+    let encoding = encoding.as_deref()?;
+    let files: Vec<&str> = encoding.split(',').map(str::trim).collect();
+    let looks_like_files = match files.as_slice() {
+        [file] => file.ends_with(".json") || Path::new(file).is_file(),
+        [_, _] => true,
+        _ => false,
+    };
+    looks_like_files.then_some(files)
+}
+
+/// Loads a HuggingFace tokenizer from a `tokenizer.json`, or builds a byte-level
+/// BPE tokenizer from a `vocab.json` and `merges.txt` pair.
+fn load_huggingface_tokenizer(files: &[&str]) -> Result<tokenizers::Tokenizer> {
+    match files {
+        [tokenizer_json] => tokenizers::Tokenizer::from_file(tokenizer_json)
+            .map_err(|e| anyhow!("Failed to load tokenizer from {}: {}", tokenizer_json, e)),
+        [vocab, merges] => {
+            let model = BPE::from_file(vocab, merges).build().map_err(|e| {
+                anyhow!(
+                    "Failed to load BPE tokenizer from {} and {}: {}",
+                    vocab,
+                    merges,
+                    e
+                )
+            })?;
+            let mut tokenizer = tokenizers::Tokenizer::new(model);
+            tokenizer.with_pre_tokenizer(Some(ByteLevel::default()));
+            Ok(tokenizer)
+        }
+        _ => Err(anyhow!(
+            "Expected a tokenizer.json or a vocab.json,merges.txt pair"
+        )),
+    }
+}
+
+/// Maps an encoding name (or alias such as "cl100k_base") to its canonical name.
+fn normalize_encoding(encoding: &Option<String>) -> Result<&'static str> {
+    match encoding.as_deref().unwrap_or("cl100k") {
+        "o200k" | "o200k_base" => Ok("o200k"),
+        "cl100k" | "cl100k_base" => Ok("cl100k"),
+        "p50k" | "p50k_base" => Ok("p50k"),
+        "p50k_edit" => Ok("p50k_edit"),
+        "r50k" | "r50k_base" | "gpt2" => Ok("r50k"),
+        other => Err(anyhow!(
+            "Unknown encoding '{}'. Valid choices: {}, or a path to a tokenizer.json",
+            other,
+            SUPPORTED_ENCODINGS
+        )),
+    }
+}
+
+/// A file that was dropped or truncated to fit the token budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetCut {
+    /// The path of the file, as it appears in the prompt.
+    pub path: String,
+    /// The number of tokens the file's code block had before it was cut.
+    pub original_tokens: usize,
+    /// The number of tokens that were kept (`0` if the file was dropped).
+    pub kept_tokens: usize,
+}
+
+/// Number of tokens reserved for the truncation marker when shortening a file.
+const TRUNCATION_SLACK: usize = 16;
+
+/// Drops or truncates files until the rendered output fits in `max_tokens`.
+///
+/// Larger files are considered lower priority: they are cut first, so as many
+/// files as possible survive. A file is truncated on line boundaries instead of
+/// being dropped when only a small part of it needs to go.
+///
+/// The cuts are worked out from each file's own token count (its `tokens` field,
+/// if it has one), and the output is only rendered again to check the result. If
+/// it still doesn't fit, e.g. because tokens merge across file boundaries, the
+/// remaining overflow is cut the same way.
+///
+/// # Arguments
+///
+/// * `folders` - The per-folder template data, each holding a `files` array.
+/// * `max_tokens` - The maximum number of tokens allowed in the rendered output.
+/// * `tokenizer` - The tokenizer used to count tokens.
+/// * `render` - Renders the full output from the (possibly trimmed) folder data.
+///
+/// # Returns
+///
+/// * `Result<(String, Vec<BudgetCut>)>` - The rendered output and the files that were cut.
+pub fn fit_to_token_budget<F>(
+    folders: &mut [serde_json::Value],
+    max_tokens: usize,
+    tokenizer: &Tokenizer,
+    mut render: F,
+) -> Result<(String, Vec<BudgetCut>)>
+where
+    F: FnMut(&[serde_json::Value]) -> Result<String>,
+{
+    let mut cuts: Vec<BudgetCut> = Vec::new();
+    let mut base_tokens: Option<usize> = None;
+
+    loop {
+        let rendered = render(folders)?;
+        let total = tokenizer.count(&rendered)?;
+        if total <= max_tokens {
+            return Ok((rendered, cuts));
+        }
+
+        // The part of the prompt that isn't file content, measured once
+        let base = match base_tokens {
+            Some(base) => base,
+            None => {
+                let taken: Vec<Option<serde_json::Value>> = folders
+                    .iter_mut()
+                    .map(|folder| {
+                        folder
+                            .get_mut("files")
+                            .map(|files| std::mem::replace(files, serde_json::json!([])))
+                    })
+                    .collect();
+                let rendered = render(folders);
+                for (folder, files) in folders.iter_mut().zip(taken) {
+                    if let Some(files) = files {
+                        folder["files"] = files;
+                    }
+                }
+                let base = tokenizer.count(&rendered?)?;
+                base_tokens = Some(base);
+                base
+            }
+        };
+
+        // (folder index, file index, tokens) of every file, largest first
+        let mut files: Vec<(usize, usize, usize)> = Vec::new();
+        for (i, folder) in folders.iter().enumerate() {
+            for (j, file) in folder["files"].as_array().into_iter().flatten().enumerate() {
+                let tokens = match file["tokens"].as_u64() {
+                    Some(tokens) => tokens as usize,
+                    None => tokenizer.count(file["code"].as_str().unwrap_or(""))?,
+                };
+                files.push((i, j, tokens));
+            }
+        }
+        if base > max_tokens || files.is_empty() {
+            return Err(anyhow!(
+                "Prompt is {} tokens even without any files, which exceeds the limit of {}",
+                base.min(total),
+                max_tokens
+            ));
+        }
+        files.sort_by_key(|&(_, _, tokens)| std::cmp::Reverse(tokens));
+
+        // The template adds its own tokens around each file (headers, indentation),
+        // so scale the overflow down to the files' own token counts
+        let file_tokens: usize = files.iter().map(|&(_, _, tokens)| tokens).sum();
+        let scale = if file_tokens == 0 {
+            1.0
+        } else {
+            ((total.saturating_sub(base)) as f64 / file_tokens as f64).max(1.0)
+        };
+
+        let mut overflow = ((total - max_tokens) as f64 / scale).ceil() as usize;
+        let mut dropped: Vec<(usize, usize)> = Vec::new();
+        for (i, j, tokens) in files {
+            if overflow == 0 {
+                break;
+            }
+            let file = &mut folders[i]["files"][j];
+            let path = file["path"].as_str().unwrap_or("").to_string();
+
+            if overflow < tokens / 2 {
+                let code = file["code"].as_str().unwrap_or("");
+                let keep = (tokens - overflow).saturating_sub(TRUNCATION_SLACK);
+                let shortened = truncate_code(code, keep, tokenizer)?;
+                let kept_tokens = tokenizer.count(&shortened)?;
+                if kept_tokens < tokens {
+                    file["code"] = serde_json::Value::String(shortened);
+                    file["tokens"] = serde_json::Value::from(kept_tokens);
+                    record_cut(&mut cuts, path, tokens, kept_tokens);
+                    overflow = overflow.saturating_sub(tokens - kept_tokens);
+                    continue;
+                }
+            }
+
+            dropped.push((i, j));
+            record_cut(&mut cuts, path, tokens, 0);
+            overflow = overflow.saturating_sub(tokens);
+        }
+
+        // Remove the dropped files from the back, so the indices stay valid
+        dropped.sort_unstable();
+        for (i, j) in dropped.into_iter().rev() {
+            if let Some(files) = folders[i]["files"].as_array_mut() {
+                files.remove(j);
+            }
+        }
+    }
+}
+
+/// Records a cut, merging it with an earlier cut of the same file.
+fn record_cut(cuts: &mut Vec<BudgetCut>, path: String, tokens: usize, kept_tokens: usize) {
+    if let Some(cut) = cuts.iter_mut().find(|cut| cut.path == path) {
+        cut.kept_tokens = kept_tokens;
+    } else {
+        cuts.push(BudgetCut {
+            path,
+            original_tokens: tokens,
+            kept_tokens,
+        });
+    }
+}
+
+/// Keeps the leading lines of a code block that fit in `max_tokens`, preserving
+/// the surrounding fence and appending a truncation marker.
+fn truncate_code(code: &str, max_tokens: usize, tokenizer: &Tokenizer) -> Result<String> {
+    let (header, body) = split_fence(code);
+
+    let total_lines = body.lines().count();
+    let mut kept = String::new();
+    let mut kept_lines = 0;
+    let mut used = 0;
+    for line in body.lines() {
+        let line_tokens = tokenizer.count(line)? + 1;
+        if used + line_tokens > max_tokens {
+            break;
+        }
+        used += line_tokens;
+        kept.push_str(line);
+        kept.push('\n');
+        kept_lines += 1;
+    }
+    kept.push_str(&format!(
+        "... [truncated {} lines] ...",
+        total_lines - kept_lines
+    ));
+
+    Ok(refence(header, &kept))
+}
+
+/// Splits a code block on line boundaries into pieces of at most `max_tokens`,
+/// each wrapped in the original fence.
+///
+/// Returns each piece along with its first and last line number. A single line
+/// longer than `max_tokens` is split between characters into pieces of its own.
+fn split_code(
+    code: &str,
+    max_tokens: usize,
+    tokenizer: &Tokenizer,
+) -> Result<Vec<(String, usize, usize)>> {
+    let (header, body) = split_fence(code);
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut first_line = 1;
+    let mut used = 0;
+    for (index, line) in body.lines().enumerate() {
+        let line_tokens = tokenizer.count(line)? + 1;
+        if used + line_tokens > max_tokens && !current.is_empty() {
+            pieces.push((refence(header, current.trim_end()), first_line, index));
+            current.clear();
+            first_line = index + 1;
+            used = 0;
+        }
+        if line_tokens > max_tokens {
+            for chunk in split_line(line, max_tokens.saturating_sub(1), tokenizer)? {
+                pieces.push((refence(header, chunk), index + 1, index + 1));
+            }
+            first_line = index + 2;
+            continue;
+        }
+        used += line_tokens;
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.is_empty() {
+        let last_line = body.lines().count();
+        pieces.push((refence(header, current.trim_end()), first_line, last_line));
+    }
+    Ok(pieces)
+}
+
+/// Splits a line between characters into chunks of at most `max_tokens` each
+/// (or a single character, if even that doesn't fit).
+fn split_line<'a>(line: &'a str, max_tokens: usize, tokenizer: &Tokenizer) -> Result<Vec<&'a str>> {
+    let mut chunks = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        if tokenizer.count(rest)? <= max_tokens {
+            chunks.push(rest);
+            break;
+        }
+
+        // Binary search for the longest prefix that fits: `ends[fits]` is the end of
+        // the longest one known to fit, `ends[high]` of the shortest known not to
+        let ends: Vec<usize> = rest
+            .char_indices()
+            .map(|(start, c)| start + c.len_utf8())
+            .collect();
+        let mut fits = 0;
+        let mut high = ends.len() - 1;
+        while fits + 1 < high {
+            let mid = (fits + high) / 2;
+            if tokenizer.count(&rest[..ends[mid]])? <= max_tokens {
+                fits = mid;
+            } else {
+                high = mid;
+            }
+        }
+        chunks.push(&rest[..ends[fits]]);
+        rest = &rest[ends[fits]..];
+    }
+    Ok(chunks)
+}
+
+/// Separates the opening fence line (if any) of a code block from its body.
+fn split_fence(code: &str) -> (Option<&str>, &str) {
+    let fence = "`".repeat(3);
+    let fenced = code.starts_with(&fence) && code.ends_with(&fence) && code.lines().count() >= 2;
+    if !fenced {
+        return (None, code);
+    }
+
+    let first_newline = code.find('\n').unwrap_or(code.len());
+    let body_end = code.len() - fence.len();
+    let body = code.get(first_newline + 1..body_end).unwrap_or("");
+    (Some(&code[..first_newline]), body)
+}
+
+/// Wraps `body` back in the fence returned by `split_fence`.
+fn refence(header: Option<&str>, body: &str) -> String {
+    match header {
+        Some(header) => format!("{}\n{}\n{}", header, body, "`".repeat(3)),
+        None => body.to_string(),
+    }
+}
+
+/// Number of tokens reserved for the "Part i of K" header and per-file template text.
+const PART_SLACK: usize = 32;
+
+/// Template data keys that are only kept in the first part of a split prompt.
+const FIRST_PART_ONLY_KEYS: &[&str] = &[
+    "git_diff",
+    "git_diff_staged",
+    "git_diff_unstaged",
+    "git_diff_all",
+    "diff_files",
+    "git_diff_branch",
+    "diff_files_branch",
+    "git_log_branch",
+    "commits",
+];
+
+/// Partitions the files into several prompts that each render to at most `max_tokens`.
+///
+/// Files are kept whole unless a single file doesn't fit in a part on its own, in
+/// which case it is split on line boundaries. Every part repeats the rest of the
+/// template data (such as the source tree), except for the git sections, which
+/// only appear in the first part. Each part starts with a "Part i of K" header.
+///
+/// # Arguments
+///
+/// * `folders` - The per-folder template data, each holding a `files` array.
+/// * `max_tokens` - The maximum number of tokens allowed in each part.
+/// * `tokenizer` - The tokenizer used to count tokens.
+/// * `render` - Renders one prompt from folder data.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>>` - The rendered parts, in order.
+pub fn split_into_parts<F>(
+    folders: &[serde_json::Value],
+    max_tokens: usize,
+    tokenizer: &Tokenizer,
+    mut render: F,
+) -> Result<Vec<String>>
+where
+    F: FnMut(&[serde_json::Value]) -> Result<String>,
+{
+    let without_files: Vec<serde_json::Value> = folders
+        .iter()
+        .map(|folder| {
+            let mut folder = folder.clone();
+            folder["files"] = serde_json::Value::Array(Vec::new());
+            folder
+        })
+        .collect();
+    let overhead = tokenizer.count(&render(&without_files)?)? + PART_SLACK;
+    if overhead >= max_tokens {
+        return Err(anyhow!(
+            "Each part needs {} tokens before any file is added, which exceeds the limit of {}",
+            overhead,
+            max_tokens
+        ));
+    }
+    let file_budget = max_tokens - overhead;
+
+    // Flatten the files, splitting any that can't fit in a part on their own
+    let mut items: Vec<(usize, serde_json::Value, usize)> = Vec::new();
+    for (i, folder) in folders.iter().enumerate() {
+        for file in folder["files"].as_array().into_iter().flatten() {
+            let code = file["code"].as_str().unwrap_or("");
+            let path = file["path"].as_str().unwrap_or("");
+            let tokens = tokenizer.count(code)? + tokenizer.count(path)?;
+            if tokens <= file_budget {
+                items.push((i, file.clone(), tokens));
+                continue;
+            }
+            for (piece, first_line, last_line) in split_code(code, file_budget, tokenizer)? {
+                let mut file = file.clone();
+                let piece_tokens = tokenizer.count(&piece)?;
+                file["path"] = serde_json::Value::String(if first_line == last_line {
+                    format!("{} (line {})", path, first_line)
+                } else {
+                    format!("{} (lines {}-{})", path, first_line, last_line)
+                });
+                file["tokens"] = serde_json::Value::from(piece_tokens);
+                file["code"] = serde_json::Value::String(piece);
+                items.push((i, file, piece_tokens));
+            }
+        }
+    }
+
+    // Greedily pack the files, based on their own token counts
+    let mut parts: Vec<Vec<(usize, serde_json::Value)>> = Vec::new();
+    let mut current: Vec<(usize, serde_json::Value)> = Vec::new();
+    let mut used = 0;
+    for (i, file, tokens) in items {
+        if used + tokens > file_budget && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+            used = 0;
+        }
+        used += tokens;
+        current.push((i, file));
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+
+    // Check each part against the real rendering, pushing files into the next
+    // part until it fits
+    let build = |index: usize, part: &[(usize, serde_json::Value)]| {
+        let mut data = without_files.clone();
+        for (i, file) in part {
+            if let Some(files) = data[*i]["files"].as_array_mut() {
+                files.push(file.clone());
+            }
+        }
+        if index > 0 {
+            for folder in data.iter_mut() {
+                for key in FIRST_PART_ONLY_KEYS {
+                    match folder.get(key) {
+                        Some(serde_json::Value::Array(_)) => {
+                            folder[key] = serde_json::Value::Array(Vec::new())
+                        }
+                        Some(_) => folder[key] = serde_json::Value::String(String::new()),
+                        None => {}
+                    }
+                }
+            }
+        }
+        data
+    };
+    let mut index = 0;
+    while index < parts.len() {
+        while parts[index].len() > 1
+            && tokenizer.count(&render(&build(index, &parts[index]))?)? + PART_SLACK > max_tokens
+        {
+            let moved = parts[index].pop().unwrap();
+            if index + 1 == parts.len() {
+                parts.push(Vec::new());
+            }
+            parts[index + 1].insert(0, moved);
+        }
+        index += 1;
+    }
+
+    let total = parts.len();
+    parts
+        .iter()
+        .enumerate()
+        .map(|(index, part)| {
+            let rendered = render(&build(index, part))?;
+            Ok(format!("Part {} of {}\n\n{}", index + 1, total, rendered))
+        })
+        .collect()
 }
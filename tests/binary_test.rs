@@ -0,0 +1,50 @@
+use code2prompt::binary::{binary_type, is_binary};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_binary_detects_nul_bytes_and_magic_numbers() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert!(is_binary(png));
+        assert_eq!(binary_type(png), "image/png");
+
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(64, 0);
+        assert!(is_binary(&elf));
+        assert_eq!(binary_type(&elf), "application/x-executable");
+
+        let wasm = b"\0asm\x01\0\0\0";
+        assert!(is_binary(wasm));
+        assert_eq!(binary_type(wasm), "application/wasm");
+
+        let noise: Vec<u8> = (0..=255u8).cycle().skip(1).take(1024).collect();
+        assert!(is_binary(&noise));
+        assert_eq!(binary_type(b"\x01\x02\x03"), "data");
+    }
+
+    #[test]
+    fn test_is_binary_accepts_text() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary(b"fn main() {\n    println!(\"hi\");\n}\n"));
+        assert!(!is_binary("caf\u{e9} na\u{ef}ve \u{1f980}\n".as_bytes()));
+        // Latin-1 text is mostly ASCII
+        assert!(!is_binary(b"caf\xe9 cr\xe8me br\xfbl\xe9e\n"));
+        // Text that happens to start with a magic number
+        assert!(!is_binary(b"MZ is the DOS executable signature\n"));
+        // UTF-16 text is full of NUL bytes, but has a byte order mark
+        assert!(!is_binary(b"\xff\xfeh\0i\0\n\0"));
+    }
+
+    #[test]
+    fn test_is_binary_accepts_cjk_legacy_encodings() {
+        // Shift_JIS and GBK text is mostly invalid UTF-8
+        let shift_jis = b"// \x93\xfa\x96{\x8c\xea\x82\xcc\x83R\x83\x81\x83\x93\x83g\x82\xc5\x82\xb7\x81B\x83t\x83@\x83C\x83\x8b\x82\xf0\x93\xc7\x82\xdd\x8d\x9e\x82\xdd\x82\xdc\x82\xb7\x81B\nfn main() {}\n";
+        assert!(!is_binary(shift_jis));
+        let gbk = b"// \xd5\xe2\xca\xc7\xd2\xbb\xb8\xf6\xd6\xd0\xce\xc4\xd7\xa2\xca\xcd\xa3\xac\xd3\xc3\xd3\xda\xb6\xc1\xc8\xa1\xce\xc4\xbc\xfe\xa1\xa3\nfn main() {}\n";
+        assert!(!is_binary(gbk));
+        // Cut off in the middle of a character
+        assert!(!is_binary(&shift_jis[..24]));
+    }
+}
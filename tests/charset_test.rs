@@ -0,0 +1,57 @@
+use code2prompt::charset::{decode_text, detect_utf16, encoding_for_label};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn test_decode_text_keeps_utf8() {
+        let (text, encoding) = decode_text("naïve 🦀\n".as_bytes(), None);
+        assert_eq!(text, "naïve 🦀\n");
+        assert_eq!(encoding, None);
+
+        // A UTF-8 byte order mark is stripped without a note
+        let (text, encoding) = decode_text(b"\xef\xbb\xbfplain\n", None);
+        assert_eq!(text, "plain\n");
+        assert_eq!(encoding, None);
+    }
+
+    #[test]
+    fn test_decode_text_detects_utf16() {
+        let mut with_bom = vec![0xFF, 0xFE];
+        with_bom.extend(utf16le("#pragma once // héllo\n"));
+        let (text, encoding) = decode_text(&with_bom, None);
+        assert_eq!(text, "#pragma once // héllo\n");
+        assert_eq!(encoding, Some("UTF-16LE"));
+
+        let without_bom = utf16le("int x = 1; // naïve\n");
+        assert!(detect_utf16(&without_bom).is_some());
+        let (text, encoding) = decode_text(&without_bom, None);
+        assert_eq!(text, "int x = 1; // naïve\n");
+        assert_eq!(encoding, Some("UTF-16LE"));
+
+        assert!(detect_utf16(b"plain ascii text").is_none());
+    }
+
+    #[test]
+    fn test_decode_text_detects_legacy_encodings() {
+        let latin1 = b"// caf\xe9 cr\xe8me br\xfbl\xe9e et na\xefvet\xe9\n";
+        let (text, encoding) = decode_text(latin1, None);
+        assert_eq!(text, "// café crème brûlée et naïveté\n");
+        assert_eq!(encoding, Some("windows-1252"));
+    }
+
+    #[test]
+    fn test_decode_text_with_input_encoding() {
+        let koi8 = encoding_for_label("koi8-r").expect("koi8-r should be known");
+        let (text, encoding) = decode_text(b"\xf0\xd2\xc9\xd7\xc5\xd4\n", Some(koi8));
+        assert_eq!(text, "Привет\n");
+        assert_eq!(encoding, Some("KOI8-R"));
+
+        assert!(encoding_for_label("klingon").is_err());
+    }
+}
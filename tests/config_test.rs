@@ -0,0 +1,116 @@
+use code2prompt::config::{find_repo_config, load_config, CONFIG_FILE_NAME};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_repo_config_searches_parent_directories() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let nested = temp_dir.path().join("src").join("nested");
+        fs::create_dir_all(&nested).expect("Failed to create nested dir");
+        assert!(find_repo_config(&nested).is_none());
+
+        fs::write(temp_dir.path().join(CONFIG_FILE_NAME), "").expect("Failed to write config");
+        let found = find_repo_config(&nested).expect("Config should be found in a parent");
+        assert_eq!(
+            found,
+            temp_dir
+                .path()
+                .canonicalize()
+                .unwrap()
+                .join(CONFIG_FILE_NAME)
+        );
+    }
+
+    #[test]
+    fn test_load_config_merges_files_and_profiles() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let user = temp_dir.path().join("user.toml");
+        let repo = temp_dir.path().join("repo.toml");
+        fs::write(
+            &user,
+            r#"
+encoding = "o200k"
+line-number = true
+
+[profiles.review]
+diff = true
+template = "user.hbs"
+"#,
+        )
+        .expect("Failed to write user config");
+        fs::write(
+            &repo,
+            r#"
+include = ["*.rs", "*.toml"]
+line-number = false
+
+[profiles.review]
+template = "review.hbs"
+"#,
+        )
+        .expect("Failed to write repo config");
+        let files = vec![user, repo, temp_dir.path().join("missing.toml")];
+
+        let settings = load_config(&files, None).expect("Failed to load config");
+        assert_eq!(settings["encoding"].as_str(), Some("o200k"));
+        assert_eq!(settings["line-number"].as_bool(), Some(false));
+        assert_eq!(settings["include"].as_array().map(Vec::len), Some(2));
+        assert!(!settings.contains_key("profiles"));
+
+        let settings = load_config(&files, Some("review")).expect("Failed to load profile");
+        assert_eq!(settings["diff"].as_bool(), Some(true));
+        assert_eq!(settings["template"].as_str(), Some("review.hbs"));
+        assert_eq!(settings["encoding"].as_str(), Some("o200k"));
+
+        let Err(err) = load_config(&files, Some("release")) else {
+            panic!("Unknown profiles should be an error");
+        };
+        assert!(err.to_string().contains("available: review"));
+    }
+
+    #[test]
+    fn test_load_config_resolves_paths_against_the_config_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config_dir = temp_dir.path().join("project");
+        fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+        fs::write(config_dir.join("review.hbs"), "{{absolute_code_path}}")
+            .expect("Failed to write template");
+        let config = config_dir.join(CONFIG_FILE_NAME);
+        fs::write(
+            &config,
+            r#"
+output = "prompt.txt"
+var-file = ["vars.toml", "/etc/vars.json"]
+var = ["notes=@notes.md", "title=Q3 report"]
+template = "document-the-code"
+
+[profiles.review]
+template = "review.hbs"
+output = "-"
+"#,
+        )
+        .expect("Failed to write config");
+        let files = vec![config];
+
+        let settings = load_config(&files, None).expect("Failed to load config");
+        let output = config_dir.join("prompt.txt");
+        assert_eq!(settings["output"].as_str(), output.to_str());
+        let var_files = settings["var-file"].as_array().unwrap();
+        assert_eq!(var_files[0].as_str(), config_dir.join("vars.toml").to_str());
+        assert_eq!(var_files[1].as_str(), Some("/etc/vars.json"));
+        let vars = settings["var"].as_array().unwrap();
+        let notes = format!("notes=@{}", config_dir.join("notes.md").display());
+        assert_eq!(vars[0].as_str(), Some(notes.as_str()));
+        assert_eq!(vars[1].as_str(), Some("title=Q3 report"));
+        assert_eq!(settings["template"].as_str(), Some("document-the-code"));
+
+        let settings = load_config(&files, Some("review")).expect("Failed to load profile");
+        let template = config_dir.join("review.hbs");
+        assert_eq!(settings["template"].as_str(), template.to_str());
+        assert_eq!(settings["output"].as_str(), Some("-"));
+    }
+}
@@ -1,12 +1,50 @@
-use code2prompt::git::{get_git_diff, get_git_diff_between_branches, get_git_log};
+use code2prompt::git::{
+    format_commits, get_changed_files, get_diff_files_between_revisions, get_git_commits,
+    get_git_diff, get_git_diff_and_files_between_revisions, get_git_diff_between_branches,
+    get_git_diff_between_revisions, get_git_log, get_git_log_between_revisions, get_tracked_files,
+    get_working_tree_diff, get_working_tree_diff_and_files, get_working_tree_diff_files, Blamer,
+    ChangeType, DiffMode, FileHistory, RevisionRange, Snapshot,
+};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use git2::{Repository, RepositoryInitOptions, Signature};
+    use git2::{Oid, Repository, RepositoryInitOptions, Signature};
     use std::fs;
+    use std::path::{Path, PathBuf};
     use tempfile::TempDir;
 
+    /// Writes the files, stages them and commits them on HEAD
+    fn commit_files(repo: &Repository, files: &[(&str, &str)], message: &str) -> Oid {
+        let repo_path = repo.workdir().expect("Repository has no workdir");
+        let mut index = repo.index().expect("Failed to get repository index");
+        for (name, content) in files {
+            let file_path = repo_path.join(name);
+            fs::create_dir_all(file_path.parent().unwrap()).expect("Failed to create dir");
+            fs::write(&file_path, content).expect("Failed to write test file");
+            index
+                .add_path(Path::new(name))
+                .expect("Failed to add file to index");
+        }
+        index.write().expect("Failed to write index");
+
+        let tree_id = index.write_tree().expect("Failed to write tree");
+        let tree = repo.find_tree(tree_id).expect("Failed to find tree");
+        let signature =
+            Signature::now("Test", "test@example.com").expect("Failed to create signature");
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .expect("Failed to commit")
+    }
+
     #[test]
     fn test_get_git_diff() {
         // Create a temporary directory
@@ -264,4 +302,613 @@ mod tests {
         assert!(log.contains("First commit in development"));
         assert!(log.contains("Second commit in development"));
     }
+
+    #[test]
+    fn test_get_changed_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path().canonicalize().unwrap();
+        let repo = Repository::init(&repo_path).expect("Failed to initialize repository");
+
+        commit_files(
+            &repo,
+            &[("a.txt", "a"), ("src/b.txt", "b"), ("c.txt", "c")],
+            "Initial commit",
+        );
+        commit_files(&repo, &[("src/b.txt", "b2")], "Change b");
+        fs::write(repo_path.join("a.txt"), "a2").expect("Failed to modify a.txt");
+        fs::write(repo_path.join("src/new.txt"), "new").expect("Failed to write new.txt");
+        fs::remove_file(repo_path.join("c.txt")).expect("Failed to delete c.txt");
+
+        // Working tree and index changes, including untracked files but not deleted ones
+        let changed = get_changed_files(&repo_path.join("src"), None).unwrap();
+        let mut expected = vec![repo_path.join("a.txt"), repo_path.join("src/new.txt")];
+        let mut changed: Vec<_> = changed.into_iter().collect();
+        changed.sort();
+        expected.sort();
+        assert_eq!(changed, expected);
+
+        // Committed changes since a revision
+        let changed = get_changed_files(&repo_path, Some("HEAD~1")).unwrap();
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            vec![repo_path.join("src/b.txt")]
+        );
+
+        assert!(get_changed_files(&repo_path, Some("no-such-ref")).is_err());
+    }
+
+    #[test]
+    fn test_get_working_tree_diff_modes() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        let numbers: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        commit_files(
+            &repo,
+            &[("staged.txt", "staged before\n"), ("numbers.txt", &numbers)],
+            "Initial commit",
+        );
+
+        // Stage one change, leave another unstaged and add an untracked file
+        fs::write(repo_path.join("staged.txt"), "staged after\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+        fs::write(
+            repo_path.join("numbers.txt"),
+            numbers.replace("line 5\n", "line five\n"),
+        )
+        .unwrap();
+        fs::write(repo_path.join("untracked.txt"), "brand new\n").unwrap();
+
+        let staged = get_working_tree_diff(repo_path, DiffMode::Staged, 3).unwrap();
+        assert!(staged.contains("-staged before"));
+        assert!(staged.contains("+staged after"));
+        assert!(!staged.contains("line five"));
+        assert_eq!(staged, get_git_diff(repo_path).unwrap());
+
+        let unstaged = get_working_tree_diff(repo_path, DiffMode::Unstaged, 3).unwrap();
+        assert!(unstaged.contains("-line 5"));
+        assert!(unstaged.contains("+line five"));
+        assert!(unstaged.contains("\n line 2\n"));
+        assert!(!unstaged.contains("staged after"));
+        assert!(!unstaged.contains("brand new"));
+
+        let no_context = get_working_tree_diff(repo_path, DiffMode::Unstaged, 0).unwrap();
+        assert!(!no_context.contains("\n line 4\n"));
+
+        let all = get_working_tree_diff(repo_path, DiffMode::All, 3).unwrap();
+        assert!(all.contains("+staged after"));
+        assert!(all.contains("+line five"));
+        assert!(all.contains("+brand new"));
+
+        assert!("everything".parse::<DiffMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_revision_range() {
+        let range: RevisionRange = "origin/main...HEAD".parse().unwrap();
+        assert_eq!(
+            range,
+            RevisionRange {
+                from: "origin/main".to_string(),
+                to: "HEAD".to_string(),
+                merge_base: true,
+            }
+        );
+
+        let range: RevisionRange = "v1.0..HEAD~1".parse().unwrap();
+        assert_eq!((range.from.as_str(), range.to.as_str()), ("v1.0", "HEAD~1"));
+        assert!(!range.merge_base);
+
+        let range: RevisionRange = "master, feature".parse().unwrap();
+        assert_eq!(
+            (range.from.as_str(), range.to.as_str()),
+            ("master", "feature")
+        );
+        assert!(!range.merge_base);
+
+        for invalid in ["main", "main..", "...HEAD", "a,b,c"] {
+            assert!(invalid.parse::<RevisionRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_git_diff_and_log_between_revisions() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let mut opts = RepositoryInitOptions::new();
+        opts.initial_head("main");
+        let repo =
+            Repository::init_opts(repo_path, &opts).expect("Failed to initialize repository");
+
+        let base = commit_files(&repo, &[("file.txt", "base\n")], "Base commit");
+        repo.tag_lightweight("v1.0", &repo.find_object(base, None).unwrap(), false)
+            .unwrap();
+
+        // Fork a feature branch, then move main on
+        repo.branch("feature", &repo.find_commit(base).unwrap(), false)
+            .unwrap();
+        let main_commit = commit_files(&repo, &[("main.txt", "on main\n")], "Main commit");
+        repo.set_head("refs/heads/feature").unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        let feature_commit =
+            commit_files(&repo, &[("feature.txt", "on feature\n")], "Feature commit");
+
+        // Tags, SHAs and relative revisions all resolve
+        let range = RevisionRange {
+            from: "v1.0".to_string(),
+            to: feature_commit.to_string(),
+            merge_base: false,
+        };
+        let diff = get_git_diff_between_revisions(repo_path, &range).unwrap();
+        assert!(diff.contains("+on feature"));
+        let log =
+            get_git_log_between_revisions(repo_path, &"HEAD~1..HEAD".parse().unwrap()).unwrap();
+        assert!(log.contains("Feature commit"));
+        assert!(!log.contains("Base commit"));
+
+        // A two-dot diff also shows main's changes as removals, a three-dot diff doesn't
+        let two_dot =
+            get_git_diff_between_revisions(repo_path, &"main..feature".parse().unwrap()).unwrap();
+        assert!(two_dot.contains("-on main"));
+        let three_dot =
+            get_git_diff_between_revisions(repo_path, &"main...feature".parse().unwrap()).unwrap();
+        assert!(three_dot.contains("+on feature"));
+        assert!(!three_dot.contains("on main"));
+        let log =
+            get_git_log_between_revisions(repo_path, &"main...feature".parse().unwrap()).unwrap();
+        assert!(log.contains("Feature commit"));
+        assert!(!log.contains("Main commit"));
+
+        // A branch that only exists on the remote is found by its short name
+        repo.reference("refs/remotes/origin/release", main_commit, true, "")
+            .unwrap();
+        let log =
+            get_git_log_between_revisions(repo_path, &"feature,release".parse().unwrap()).unwrap();
+        assert!(log.contains("Main commit"));
+
+        assert!(
+            get_git_diff_between_revisions(repo_path, &"main..no-such-rev".parse().unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_get_git_commits() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        commit_files(&repo, &[("a.txt", "one\ntwo\n")], "Initial commit");
+        commit_files(
+            &repo,
+            &[("a.txt", "one\n2\n3\n"), ("b.txt", "new\n")],
+            "Change a and add b\n\nThe body explains why.\n\nIn two paragraphs.\n",
+        );
+
+        let commits = get_git_commits(repo_path, &"HEAD~1..HEAD".parse().unwrap()).unwrap();
+        assert_eq!(commits.len(), 1);
+        let commit = &commits[0];
+        assert_eq!(commit.author, "Test");
+        assert_eq!(commit.email, "test@example.com");
+        assert_eq!(commit.summary, "Change a and add b");
+        assert_eq!(commit.body, "The body explains why.\n\nIn two paragraphs.");
+        assert!(commit.date.contains('T'));
+        assert_eq!(commit.files.len(), 2);
+        assert_eq!(
+            (
+                commit.files[0].path.as_str(),
+                commit.files[0].insertions,
+                commit.files[0].deletions
+            ),
+            ("a.txt", 2, 1)
+        );
+        assert_eq!((commit.insertions(), commit.deletions()), (3, 1));
+
+        let json = commit.to_json();
+        assert_eq!(json["short_sha"], commit.sha[..7]);
+        assert_eq!(json["files_changed"], 2);
+        assert_eq!(json["files"][1]["path"], "b.txt");
+
+        let log = format_commits(&commits);
+        assert!(log.starts_with(&format!(
+            "commit {}\nAuthor: Test <test@example.com>\n",
+            commit.sha
+        )));
+        assert!(log.contains(
+            "\n    Change a and add b\n\n    The body explains why.\n\n    In two paragraphs.\n"
+        ));
+        assert!(log.contains(" a.txt | +2 -1\n"));
+        assert!(log.contains(" 2 files changed, 3 insertions(+), 1 deletion(-)\n"));
+    }
+
+    #[test]
+    fn test_get_diff_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        let numbers: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        commit_files(
+            &repo,
+            &[
+                ("moved.txt", &numbers),
+                ("gone.txt", "bye\n"),
+                ("kept.txt", "one\ntwo\n"),
+            ],
+            "Initial commit",
+        );
+
+        // Rename one file, delete another, change a third and add a binary file
+        fs::rename(repo_path.join("moved.txt"), repo_path.join("renamed.txt")).unwrap();
+        fs::write(repo_path.join("renamed.txt"), format!("{}21\n", numbers)).unwrap();
+        fs::remove_file(repo_path.join("gone.txt")).unwrap();
+        fs::write(repo_path.join("kept.txt"), "one\n2\n").unwrap();
+        fs::write(repo_path.join("image.bin"), [0u8, 1, 2, 3]).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+
+        let files = get_working_tree_diff_files(repo_path, DiffMode::Staged, 3).unwrap();
+        let summary: Vec<_> = files
+            .iter()
+            .map(|file| {
+                (
+                    file.path.as_str(),
+                    file.change_type,
+                    file.additions,
+                    file.deletions,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("gone.txt", ChangeType::Deleted, 0, 1),
+                ("image.bin", ChangeType::Added, 0, 0),
+                ("kept.txt", ChangeType::Modified, 1, 1),
+                ("renamed.txt", ChangeType::Renamed, 1, 0),
+            ]
+        );
+        assert!(files[1].binary);
+        assert_eq!(files[2].hunks, "@@ -1,2 +1,2 @@\n one\n-two\n+2\n");
+        assert_eq!(files[3].old_path.as_deref(), Some("moved.txt"));
+        assert!(files[3].hunks.starts_with("@@ -18,3 +18,4 @@"));
+
+        let json = files[3].to_json();
+        assert_eq!(json["change_type"], "renamed");
+        assert_eq!(json["old_path"], "moved.txt");
+
+        // The diff and its files can be generated together
+        let (diff, diff_files) =
+            get_working_tree_diff_and_files(repo_path, DiffMode::Staged, 3).unwrap();
+        assert_eq!(
+            diff,
+            get_working_tree_diff(repo_path, DiffMode::Staged, 3).unwrap()
+        );
+        assert_eq!(diff_files, files);
+
+        // The same changes, committed, compared between revisions
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Change",
+            &tree,
+            &[&head],
+        )
+        .unwrap();
+        let committed =
+            get_diff_files_between_revisions(repo_path, &"HEAD~1..HEAD".parse().unwrap()).unwrap();
+        assert_eq!(committed, files);
+        let (diff, committed) =
+            get_git_diff_and_files_between_revisions(repo_path, &"HEAD~1..HEAD".parse().unwrap())
+                .unwrap();
+        assert!(diff.contains("+++ b/renamed.txt"));
+        assert_eq!(committed, files);
+    }
+
+    #[test]
+    fn test_blamer() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        let first = commit_files(&repo, &[("src/lib.rs", "one\ntwo\n")], "First commit");
+        let second = commit_files(&repo, &[("src/lib.rs", "one\n2\n")], "Second commit");
+
+        let blamer = Blamer::discover(&repo_path.join("src"), None).unwrap();
+        let contents = b"one\n2\nthree\n";
+        let lines = blamer
+            .blame(&repo_path.join("src/lib.rs"), contents)
+            .unwrap();
+        let shas: Vec<_> = lines.iter().map(|line| line.short_sha.clone()).collect();
+        assert_eq!(
+            shas,
+            vec![
+                first.to_string()[..7].to_string(),
+                second.to_string()[..7].to_string(),
+                "0000000".to_string(),
+            ]
+        );
+        assert_eq!(lines[0].author, "Test");
+        assert_eq!(lines[0].date.len(), "2024-01-01".len());
+        assert_eq!(lines[2].author, "Not Committed Yet");
+
+        // As of a revision, the file is blamed as it was then
+        let blamer = Blamer::discover(repo_path, Some("HEAD~1")).unwrap();
+        let lines = blamer
+            .blame(&repo_path.join("src/lib.rs"), b"one\ntwo\n")
+            .unwrap();
+        assert!(lines
+            .iter()
+            .all(|line| line.short_sha == first.to_string()[..7]));
+
+        // Untracked files aren't committed at all
+        let lines = blamer.blame(&repo_path.join("new.txt"), b"a\nb").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.short_sha == "0000000"));
+
+        let outside = TempDir::new().unwrap();
+        assert!(blamer.blame(&outside.path().join("x.txt"), b"x").is_err());
+        assert!(Blamer::discover(outside.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_file_history() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        let first = commit_files(
+            &repo,
+            &[("a.txt", "1\n"), ("b.txt", "1\n"), ("c.bin", "\0\u{1}\n")],
+            "Add a and b",
+        );
+        let second = commit_files(&repo, &[("a.txt", "2\n")], "Change a");
+        let third = commit_files(&repo, &[("a.txt", "3\n")], "Change a again");
+        fs::write(repo_path.join("untracked.txt"), "new\n").unwrap();
+
+        let history = FileHistory::discover(repo_path, None, 2).unwrap();
+        let summaries = |path: &str| -> Vec<(String, String)> {
+            history
+                .for_file(&repo_path.join(path))
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.sha, entry.summary))
+                .collect()
+        };
+        assert_eq!(
+            summaries("a.txt"),
+            vec![
+                (third.to_string(), "Change a again".to_string()),
+                (second.to_string(), "Change a".to_string()),
+            ]
+        );
+        assert_eq!(
+            summaries("b.txt"),
+            vec![(first.to_string(), "Add a and b".to_string())]
+        );
+        // Binary files have a history too
+        assert_eq!(
+            summaries("c.bin"),
+            vec![(first.to_string(), "Add a and b".to_string())]
+        );
+        assert!(summaries("untracked.txt").is_empty());
+
+        let history = FileHistory::discover(repo_path, Some("HEAD~1"), 5).unwrap();
+        let shas: Vec<_> = history
+            .for_file(&repo_path.join("a.txt"))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.sha)
+            .collect();
+        assert_eq!(shas, vec![second.to_string(), first.to_string()]);
+        let history = FileHistory::discover(repo_path, None, 2).unwrap();
+
+        let entry = &history.for_file(&repo_path.join("b.txt")).unwrap()[0];
+        assert!(entry.date.contains('T'));
+        assert_eq!(entry.to_json()["short_sha"], first.to_string()[..7]);
+
+        let outside = TempDir::new().unwrap();
+        assert!(history.for_file(&outside.path().join("x.txt")).is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        commit_files(
+            &repo,
+            &[("README.md", "old readme\n"), ("src/lib.rs", "old lib\n")],
+            "First commit",
+        );
+        commit_files(&repo, &[("src/lib.rs", "new lib\n")], "Second commit");
+        fs::remove_file(repo_path.join("README.md")).unwrap();
+
+        let snapshot = Snapshot::open(repo_path, "HEAD~1").unwrap();
+        let entries = snapshot.walk(repo_path).unwrap();
+        let paths: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.path.to_str().unwrap(), entry.is_dir))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![("README.md", false), ("src", true), ("src/lib.rs", false)]
+        );
+
+        // Files are read from the revision, even if they changed or are gone on disk
+        let lib = snapshot
+            .entry(&repo_path.join("src/lib.rs"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.read(lib.blob.unwrap()).unwrap(), b"old lib\n");
+        let readme = snapshot
+            .entry(&repo_path.join("README.md"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            snapshot.read(readme.blob.unwrap()).unwrap(),
+            b"old readme\n"
+        );
+        assert!(
+            snapshot
+                .entry(&repo_path.join("src"))
+                .unwrap()
+                .unwrap()
+                .is_dir
+        );
+        assert!(snapshot
+            .entry(&repo_path.join("nope.txt"))
+            .unwrap()
+            .is_none());
+
+        let src_entries = snapshot.walk(&repo_path.join("src")).unwrap();
+        assert_eq!(src_entries.len(), 1);
+        assert_eq!(src_entries[0].path, Path::new("lib.rs"));
+
+        assert!(Snapshot::open(repo_path, "no-such-rev").is_err());
+    }
+
+    #[test]
+    fn test_get_tracked_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path();
+        let repo = Repository::init(repo_path).expect("Failed to initialize repository");
+
+        commit_files(
+            &repo,
+            &[
+                (".gitignore", "*.tmp\n"),
+                ("Cargo.lock", "lock"),
+                ("src/lib.rs", "lib"),
+                ("src/gone.rs", "gone"),
+            ],
+            "Initial commit",
+        );
+        fs::remove_file(repo_path.join("src/gone.rs")).unwrap();
+        fs::write(repo_path.join("scratch.txt"), "scratch").unwrap();
+        fs::write(repo_path.join("notes.tmp"), "ignored").unwrap();
+
+        let workdir = repo_path.canonicalize().unwrap();
+        let relative = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.strip_prefix(&workdir).unwrap().display().to_string())
+                .collect()
+        };
+
+        let tracked = get_tracked_files(&repo_path.join("src"), false).unwrap();
+        assert_eq!(
+            relative(tracked),
+            vec![".gitignore", "Cargo.lock", "src/lib.rs"]
+        );
+
+        let with_untracked = get_tracked_files(repo_path, true).unwrap();
+        assert_eq!(
+            relative(with_untracked),
+            vec![".gitignore", "Cargo.lock", "scratch.txt", "src/lib.rs"]
+        );
+
+        let outside = TempDir::new().expect("Failed to create temp dir");
+        assert!(get_tracked_files(outside.path(), false).is_err());
+    }
+
+    #[test]
+    fn test_submodules() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let lib_path = temp_dir.path().join("lib");
+        let lib = Repository::init(&lib_path).expect("Failed to initialize repository");
+        commit_files(&lib, &[("l.rs", "pub fn l() {}\n")], "Add lib");
+
+        let app =
+            Repository::init(temp_dir.path().join("app")).expect("Failed to initialize repository");
+        let app_path = temp_dir.path().join("app").canonicalize().unwrap();
+        commit_files(&app, &[("main.rs", "fn main() {}\n")], "Add app");
+        let mut submodule = app
+            .submodule(lib_path.to_str().unwrap(), Path::new("libs/lib"), true)
+            .expect("Failed to add submodule");
+        submodule.clone(None).expect("Failed to clone submodule");
+        submodule
+            .add_finalize()
+            .expect("Failed to finalize submodule");
+        commit_files(&app, &[], "Add lib submodule");
+        app.tag_lightweight("v1", &app.revparse_single("HEAD").unwrap(), false)
+            .unwrap();
+
+        // Uncommitted changes in the submodule are part of the diff, even from a subdirectory
+        let sub_path = app_path.join("libs/lib");
+        fs::write(sub_path.join("l.rs"), "pub fn l() {}\npub fn l2() {}\n").unwrap();
+        let diff = get_working_tree_diff(&app_path.join("libs"), DiffMode::All, 3).unwrap();
+        assert!(diff.contains("diff --git a/libs/lib/l.rs b/libs/lib/l.rs"));
+        assert!(diff.contains("+pub fn l2() {}"));
+        let files = get_working_tree_diff_files(&app_path, DiffMode::All, 3).unwrap();
+        assert!(files.iter().any(|file| file.path == "libs/lib/l.rs"));
+        let changed = get_changed_files(&app_path, None).unwrap();
+        assert!(changed.contains(&sub_path.join("l.rs")));
+
+        // Committing in the submodule and bumping it brings its commits into the range
+        let sub = Repository::open(&sub_path).unwrap();
+        commit_files(
+            &sub,
+            &[("l.rs", "pub fn l() {}\npub fn l2() {}\n")],
+            "Add l2",
+        );
+        app.find_submodule("libs/lib")
+            .unwrap()
+            .add_to_index(true)
+            .unwrap();
+        commit_files(&app, &[], "Bump lib");
+
+        let range: RevisionRange = "v1..HEAD".parse().unwrap();
+        let diff = get_git_diff_between_revisions(&app_path, &range).unwrap();
+        assert!(diff.contains("+Subproject commit"));
+        assert!(diff.contains("diff --git a/libs/lib/l.rs b/libs/lib/l.rs"));
+        let log = get_git_log_between_revisions(&app_path, &range).unwrap();
+        assert!(log.contains(" - Bump lib\n\nlibs/lib:\n"));
+        assert!(log.ends_with(" - Add l2\n"));
+        let commits = get_git_commits(&app_path, &range).unwrap();
+        let summaries: Vec<_> = commits.iter().map(|c| c.summary.as_str()).collect();
+        assert_eq!(summaries, vec!["Bump lib", "Add l2"]);
+        assert_eq!(commits[1].files[0].path, "libs/lib/l.rs");
+        let changed = get_changed_files(&app_path, Some("v1")).unwrap();
+        assert!(changed.contains(&sub_path.join("l.rs")));
+
+        let tracked = get_tracked_files(&app_path, false).unwrap();
+        assert!(tracked.contains(&app_path.join("main.rs")));
+        assert!(tracked.contains(&sub_path.join("l.rs")));
+        assert!(!tracked.contains(&sub_path));
+    }
+
+    #[test]
+    fn test_nested_repositories() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        for name in ["a", "group/b"] {
+            let repo = Repository::init(root.join(name)).expect("Failed to initialize repository");
+            commit_files(&repo, &[("file.txt", "one\n")], "Initial commit");
+            fs::write(root.join(name).join("file.txt"), "two\n").unwrap();
+        }
+
+        // Outside of a repository, the repositories nested in the path are used
+        let files = get_working_tree_diff_files(root, DiffMode::Unstaged, 3).unwrap();
+        let paths: Vec<_> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["a/file.txt", "group/b/file.txt"]);
+        let diff = get_working_tree_diff(root, DiffMode::Unstaged, 3).unwrap();
+        assert!(diff.contains("--- a/group/b/file.txt"));
+
+        let empty = TempDir::new().expect("Failed to create temp dir");
+        assert!(get_working_tree_diff(empty.path(), DiffMode::All, 3).is_err());
+    }
 }
@@ -24,11 +24,13 @@ fn init_logger() {
 fn create_temp_file(dir: &Path, name: &str, content: &str) {
     let file_path = dir.join(name);
     let parent_dir = file_path.parent().unwrap();
-    fs::create_dir_all(parent_dir).expect(&format!("Failed to create directory: {:?}", parent_dir));
-    let mut file =
-        File::create(&file_path).expect(&format!("Failed to create temp file: {:?}", file_path));
+    fs::create_dir_all(parent_dir)
+        .unwrap_or_else(|_| panic!("Failed to create directory: {:?}", parent_dir));
+    let mut file = File::create(&file_path)
+        .unwrap_or_else(|_| panic!("Failed to create temp file: {:?}", file_path));
     //debug!("Writing to file: {:?}", file_path);
-    writeln!(file, "{}", content).expect(&format!("Failed to write to temp file: {:?}", file_path));
+    writeln!(file, "{}", content)
+        .unwrap_or_else(|_| panic!("Failed to write to temp file: {:?}", file_path));
 }
 
 fn create_test_hierarchy(base_path: &Path) {
@@ -65,9 +67,35 @@ fn create_test_hierarchy(base_path: &Path) {
     );
 }
 
+/// Commits the files or directories at `paths` (even ignored ones) in a repository
+fn commit_paths(repo: &git2::Repository, paths: &[&str], message: &str) -> git2::Oid {
+    let mut index = repo.index().expect("Failed to get repository index");
+    index
+        .add_all(paths, git2::IndexAddOption::FORCE, None)
+        .expect("Failed to add files to index");
+    index.write().expect("Failed to write index");
+
+    let tree_id = index.write_tree().expect("Failed to write tree");
+    let tree = repo.find_tree(tree_id).expect("Failed to find tree");
+    let signature =
+        git2::Signature::now("Ada", "ada@example.com").expect("Failed to create signature");
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .expect("Failed to commit")
+}
+
 fn read_output_file(dir: &Path, file_name: &str) -> String {
     let file_path = dir.join(file_name);
-    read_to_string(&file_path).expect(&format!("Failed to read output file: {:?}", file_path))
+    read_to_string(&file_path)
+        .unwrap_or_else(|_| panic!("Failed to read output file: {:?}", file_path))
 }
 
 mod tests {
@@ -91,10 +119,11 @@ mod tests {
         fn command(&self) -> Command {
             let mut cmd =
                 Command::cargo_bin("code2prompt").expect("Failed to find code2prompt binary");
-            cmd.arg(&self.dir.path().to_str().unwrap())
+            cmd.arg(self.dir.path().to_str().unwrap())
                 .arg("--output")
                 .arg(&self.output_file)
-                .arg("--no-clipboard");
+                .arg("--no-clipboard")
+                .env("XDG_CONFIG_HOME", self.dir.path().join(".config"));
             cmd
         }
 
@@ -322,4 +351,713 @@ mod tests {
         assert!(contains("MyApp.app").not().eval(&output));
         assert!(contains("binary payload").not().eval(&output));
     }
+
+    #[test]
+    fn test_max_tokens_drops_largest_files() {
+        let env = TestEnv::new();
+        let big = "let value = 42;\n".repeat(2000);
+        create_temp_file(env.dir.path(), "big/generated.rs", &big);
+
+        let mut cmd = env.command();
+        cmd.arg("--max-tokens=2000")
+            .assert()
+            .success()
+            .stderr(contains("Token budget:"))
+            .stderr(contains("generated.rs"));
+
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("CONTENT FOO.PY").eval(&output));
+        assert!(output.matches("let value = 42;").count() < 2000);
+    }
+
+    #[test]
+    fn test_sample_rate_keeps_one_in_n_files_per_directory() {
+        let env = TestEnv::new();
+        let mut cmd = env.command();
+        cmd.arg("--sample-rate=3").assert().success();
+
+        let output = env.read_output();
+        debug!("Test sample rate output:\n{}", output);
+        let lowercase_kept = [
+            "foo.py",
+            "bar.py",
+            "baz.py",
+            "qux.txt",
+            "corge.txt",
+            "grault.txt",
+        ]
+        .iter()
+        .filter(|name| output.contains(&format!("content {}", name)))
+        .count();
+        assert_eq!(lowercase_kept, 2);
+        assert_eq!(output.matches("[sampled out]").count(), 8);
+
+        // Sampling is seeded, so a second run picks the same files
+        let sampled_out = |output: &str| -> Vec<String> {
+            output
+                .lines()
+                .filter(|line| line.contains("[sampled out]"))
+                .map(|line| line.trim().to_string())
+                .collect()
+        };
+        let mut cmd = env.command();
+        cmd.arg("--sample-rate=3").assert().success();
+        assert_eq!(
+            sampled_out(&env.read_output())[..8],
+            sampled_out(&output)[..]
+        );
+
+        env.command()
+            .arg("--sample-rate=0")
+            .assert()
+            .failure()
+            .stderr(contains("--sample-rate"));
+    }
+
+    #[test]
+    fn test_json_output() {
+        let env = TestEnv::new();
+        let mut cmd = env.command();
+        cmd.arg("--json").arg("--include=*.py").assert().success();
+
+        let output = env.read_output();
+        let document: serde_json::Value =
+            serde_json::from_str(&output).expect("Output is not valid JSON");
+        assert_eq!(document["encoding"], "cl100k");
+        assert!(document["token_count"].as_u64().unwrap() > 0);
+        assert!(contains("content foo.py").eval(document["prompt"].as_str().unwrap()));
+
+        let roots = document["roots"].as_array().unwrap();
+        assert_eq!(roots.len(), 1);
+        let files = roots[0]["files"].as_array().unwrap();
+        assert_eq!(files.len(), 6);
+        assert!(files
+            .iter()
+            .all(|file| file["tokens"].as_u64().unwrap() > 0));
+        assert!(contains("lowercase").eval(roots[0]["source_tree"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn test_tree_tokens_and_top_files() {
+        let env = TestEnv::new();
+        let mut cmd = env.command();
+        cmd.arg("--include=*.py")
+            .arg("--tree-tokens")
+            .arg("--top-files=2")
+            .assert()
+            .success()
+            .stderr(contains("Largest files:"));
+
+        let output = env.read_output();
+        debug!("Test tree tokens output:\n{}", output);
+        assert!(predicates::str::is_match(r"foo\.py \[\d+ tokens\]")
+            .unwrap()
+            .eval(&output));
+        assert!(predicates::str::is_match(r"lowercase \[\d+ tokens\]")
+            .unwrap()
+            .eval(&output));
+        assert!(contains("qux.txt [").not().eval(&output));
+
+        // A path right after the flag isn't taken as its value
+        Command::cargo_bin("code2prompt")
+            .expect("Failed to find code2prompt binary")
+            .arg("--top-files")
+            .arg(env.dir.path().to_str().unwrap())
+            .arg("--stdout")
+            .assert()
+            .success()
+            .stderr(contains("Largest files:"));
+    }
+
+    #[test]
+    fn test_split_tokens_writes_numbered_parts() {
+        let env = TestEnv::new();
+        let big = "let value = 42;\n".repeat(400);
+        create_temp_file(env.dir.path(), "big/generated.rs", &big);
+
+        let mut cmd = env.command();
+        cmd.arg("--split-tokens=1000").assert().success();
+
+        let parts: Vec<String> = fs::read_dir(env.dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("output-"))
+            .collect();
+        assert!(parts.len() > 1, "Expected several parts, got {:?}", parts);
+
+        let total = parts.len();
+        for part in 1..=total {
+            let output =
+                read_output_file(env.dir.path(), &format!("output-{}-of-{}.txt", part, total));
+            assert!(output.starts_with(&format!("Part {} of {}", part, total)));
+            assert!(contains("Source Tree:").eval(&output));
+            assert!(contains("generated.rs").eval(&output));
+        }
+    }
+
+    #[test]
+    fn test_stdout_writes_only_the_prompt() {
+        let env = TestEnv::new();
+        let mut cmd = Command::cargo_bin("code2prompt").expect("Failed to find code2prompt binary");
+        let assert = cmd
+            .arg(env.dir.path().to_str().unwrap())
+            .arg("--stdout")
+            .arg("--include=*.py")
+            .assert()
+            .success()
+            .stderr(contains("Token count:"))
+            .stderr(contains("Done."));
+
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert!(stdout.starts_with("<context>"));
+        assert!(contains("content foo.py").eval(&stdout));
+        assert!(contains("Token count:").not().eval(&stdout));
+
+        // `-o -` is an alias for `--stdout`
+        let mut cmd = Command::cargo_bin("code2prompt").expect("Failed to find code2prompt binary");
+        let assert = cmd
+            .arg(env.dir.path().to_str().unwrap())
+            .arg("--output=-")
+            .arg("--include=*.py")
+            .assert()
+            .success();
+        let dash_stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert_eq!(dash_stdout, stdout);
+        assert!(!env.dir.path().join("-").exists());
+    }
+
+    #[test]
+    fn test_config_file_profiles_and_cli_overrides() {
+        let env = TestEnv::new();
+        create_temp_file(
+            env.dir.path(),
+            ".code2prompt.toml",
+            r#"
+include = ["*.py"]
+
+[profiles.lower]
+exclude = "**/uppercase/**"
+"#,
+        );
+
+        // Top-level config values are used as defaults
+        env.command().assert().success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("CONTENT FOO.PY").eval(&output));
+        assert!(contains("content qux.txt").not().eval(&output));
+
+        // Profiles add to and override the top-level values
+        env.command().arg("--profile=lower").assert().success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("CONTENT FOO.PY").not().eval(&output));
+
+        // Command-line flags win over the config file
+        env.command()
+            .arg("--profile=lower")
+            .arg("--include=*.txt")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("content qux.txt").eval(&output));
+        assert!(contains("CONTENT QUX.TXT").not().eval(&output));
+
+        env.command()
+            .arg("--profile=missing")
+            .assert()
+            .failure()
+            .stderr(contains("Unknown profile 'missing' (available: lower)"));
+    }
+
+    #[test]
+    fn test_config_file_paths_are_relative_to_the_config() {
+        let env = TestEnv::new();
+        create_temp_file(
+            env.dir.path(),
+            "prompts/paths.hbs",
+            "{{#each files}}PATH {{path}}\n{{/each}}",
+        );
+        create_temp_file(
+            env.dir.path(),
+            ".code2prompt.toml",
+            r#"
+template = "prompts/paths.hbs"
+output = "prompts/output.txt"
+"#,
+        );
+
+        // Run from another directory, with the path after `--`
+        Command::cargo_bin("code2prompt")
+            .unwrap()
+            .current_dir(env.dir.path().join("lowercase"))
+            .arg("--no-clipboard")
+            .arg("--")
+            .arg(env.dir.path())
+            .env("XDG_CONFIG_HOME", env.dir.path().join(".config"))
+            .assert()
+            .success();
+        let output = read_output_file(env.dir.path(), "prompts/output.txt");
+        assert!(contains("PATH ").eval(&output));
+        assert!(contains("content foo.py").not().eval(&output));
+    }
+
+    #[test]
+    fn test_grep_filters_files_by_content() {
+        let env = TestEnv::new();
+        let lines: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let mut long_file = lines.clone();
+        long_file[9] = "let gateway = PaymentGateway::new();".to_string();
+        create_temp_file(
+            env.dir.path(),
+            "lowercase/payments.rs",
+            &long_file.join("\n"),
+        );
+
+        env.command()
+            .arg("--grep=PaymentGateway")
+            .arg("--grep=CONTENT FOO")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("PaymentGateway::new()").eval(&output));
+        assert!(contains("CONTENT FOO.PY").eval(&output));
+        assert!(contains("content foo.py").not().eval(&output));
+
+        env.command()
+            .arg("--grep=PaymentGateway")
+            .arg("--grep-invert")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("PaymentGateway::new()").not().eval(&output));
+        assert!(contains("content foo.py").eval(&output));
+
+        env.command()
+            .arg("--grep=PaymentGateway")
+            .arg("--grep-context=1")
+            .arg("--line-number")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("... [8 lines omitted] ...").eval(&output));
+        assert!(contains("   9 | line 9").eval(&output));
+        assert!(contains("  10 | let gateway = PaymentGateway::new();").eval(&output));
+        assert!(contains("  11 | line 11").eval(&output));
+        assert!(contains("... [9 lines omitted] ...").eval(&output));
+        assert!(contains("line 12").not().eval(&output));
+
+        env.command().arg("--grep=(").assert().failure();
+    }
+
+    #[test]
+    fn test_max_file_size_and_lines() {
+        let env = TestEnv::new();
+        create_temp_file(
+            env.dir.path(),
+            "lowercase/generated.json",
+            &"[1, 2, 3]\n".repeat(2000),
+        );
+        let rows: Vec<String> = (1..=50).map(|i| format!("row {}", i)).collect();
+        create_temp_file(env.dir.path(), "lowercase/long.txt", &rows.join("\n"));
+
+        env.command()
+            .arg("--max-file-size=8K")
+            .arg("--max-file-lines=10")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success()
+            .stderr(contains("Skipped 1 file(s) larger than --max-file-size"))
+            .stderr(contains("generated.json"))
+            .stderr(contains("Truncated 1 file(s) longer than --max-file-lines"))
+            .stderr(contains("long.txt (10 lines kept)"));
+
+        let output = env.read_output();
+        assert!(contains("generated.json [skipped: 19.5 KB]").eval(&output));
+        assert!(contains("[1, 2, 3]").not().eval(&output));
+        assert!(contains("row 5\n").eval(&output));
+        assert!(contains("... [truncated 40 lines] ...").eval(&output));
+        assert!(contains("row 6\n").not().eval(&output));
+        assert!(contains("row 46\n").eval(&output));
+        assert!(contains("content foo.py").eval(&output));
+
+        env.command().arg("--max-file-size=10Q").assert().failure();
+    }
+
+    #[test]
+    fn test_binary_files_are_detected_by_content() {
+        let env = TestEnv::new();
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(64, 0);
+        fs::write(env.dir.path().join("lowercase/blob.dat"), elf).unwrap();
+        fs::write(
+            env.dir.path().join("lowercase/image.png"),
+            b"not really a png\n",
+        )
+        .unwrap();
+
+        env.command().assert().success();
+        let output = env.read_output();
+        assert!(contains("blob.dat [binary: application/x-executable, 64 B]").eval(&output));
+        assert!(contains("ELF").not().eval(&output));
+        assert!(contains("not really a png").eval(&output));
+
+        env.command()
+            .arg("--force-binary=*.png")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("image.png [binary: data, 17 B]").eval(&output));
+        assert!(contains("not really a png").not().eval(&output));
+    }
+
+    #[test]
+    fn test_legacy_encodings_are_transcoded() {
+        let env = TestEnv::new();
+        fs::write(
+            env.dir.path().join("lowercase/legacy.cpp"),
+            b"// caf\xe9 cr\xe8me br\xfbl\xe9e et na\xefvet\xe9\n",
+        )
+        .unwrap();
+
+        env.command().assert().success();
+        let output = env.read_output();
+        assert!(contains("legacy.cpp` (converted from windows-1252):").eval(&output));
+        assert!(contains("// café crème brûlée et naïveté").eval(&output));
+        assert!(contains("[]").not().eval(&output));
+
+        env.command()
+            .arg("--input-encoding=klingon")
+            .assert()
+            .failure()
+            .stderr(contains("Unknown input encoding 'klingon'"));
+    }
+
+    #[test]
+    fn test_input_encoding_reads_files_that_look_binary() {
+        let env = TestEnv::new();
+        // Half-width katakana in Shift_JIS, which is all invalid UTF-8
+        fs::write(
+            env.dir.path().join("lowercase/katakana.txt"),
+            b"\xba\xdd\xc6\xc1\xca \xbe\xb6\xb2\n",
+        )
+        .unwrap();
+
+        env.command().assert().success();
+        assert!(contains("katakana.txt [binary: data").eval(&env.read_output()));
+
+        env.command()
+            .arg("--input-encoding=shift_jis")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("katakana.txt` (converted from Shift_JIS):").eval(&output));
+        assert!(contains("ｺﾝﾆﾁﾊ ｾｶｲ").eval(&output));
+    }
+
+    #[test]
+    fn test_blame_annotates_lines() {
+        let env = TestEnv::new();
+        let repo = git2::Repository::init(env.dir.path()).unwrap();
+        let commit = commit_paths(&repo, &["lowercase/foo.py"], "Add foo");
+        create_temp_file(
+            env.dir.path(),
+            "lowercase/foo.py",
+            "content foo.py\nnew line",
+        );
+
+        env.command()
+            .arg("--blame")
+            .arg("--line-number")
+            .arg("--include=**/foo.py")
+            .assert()
+            .success();
+        let output = env.read_output();
+        let short_sha = &commit.to_string()[..7];
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        assert!(contains(format!(
+            "{} (Ada               {})    1 | content foo.py",
+            short_sha, date
+        ))
+        .eval(&output));
+        assert!(contains("0000000 (Not Committed Yet           )    2 | new line").eval(&output));
+
+        let outside = tempdir().unwrap();
+        create_temp_file(outside.path(), "file.txt", "content");
+        Command::cargo_bin("code2prompt")
+            .unwrap()
+            .arg(outside.path())
+            .arg("--blame")
+            .arg("--stdout")
+            .assert()
+            .failure()
+            .stderr(contains("--blame only works in a git repository"));
+    }
+
+    #[test]
+    fn test_file_history_lists_recent_commits() {
+        let env = TestEnv::new();
+        let repo = git2::Repository::init(env.dir.path()).unwrap();
+        for (content, message) in [("v1", "Add foo"), ("v2", "Tweak foo"), ("v3", "Fix foo")] {
+            create_temp_file(env.dir.path(), "lowercase/foo.py", content);
+            commit_paths(&repo, &["lowercase/foo.py"], message);
+        }
+
+        env.command()
+            .arg("--file-history=2")
+            .arg("--include=**/foo.py,**/bar.py")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("Recent commits:").eval(&output));
+        assert!(contains("Fix foo\n").eval(&output));
+        assert!(contains("Tweak foo\n").eval(&output));
+        assert!(contains("Add foo").not().eval(&output));
+        // bar.py was never committed, so it has no history section
+        assert_eq!(output.matches("Recent commits:").count(), 1);
+    }
+
+    #[test]
+    fn test_diff_modes_are_only_generated_when_used() {
+        let env = TestEnv::new();
+        let repo = git2::Repository::init(env.dir.path()).unwrap();
+        commit_paths(&repo, &["lowercase"], "v1");
+        create_temp_file(env.dir.path(), "lowercase/foo.py", "unstaged foo.py");
+
+        env.command()
+            .arg("--diff=staged")
+            .arg("--json")
+            .assert()
+            .success();
+        let json: serde_json::Value = serde_json::from_str(&env.read_output()).unwrap();
+        assert_eq!(json["roots"][0]["git_diff_unstaged"], "");
+
+        create_temp_file(
+            env.dir.path(),
+            "diffs.hbs",
+            "STAGED[{{git_diff}}] UNSTAGED[{{git_diff_unstaged}}]",
+        );
+        env.command()
+            .arg("--diff=staged")
+            .arg(format!(
+                "--template={}",
+                env.dir.path().join("diffs.hbs").display()
+            ))
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("STAGED[]").eval(&output));
+        assert!(contains("+unstaged foo.py").eval(&output));
+    }
+
+    #[test]
+    fn test_rev_reads_files_from_git() {
+        let env = TestEnv::new();
+        create_temp_file(env.dir.path(), "lowercase/.gitignore", "qux.txt");
+        create_temp_file(env.dir.path(), "uppercase/build.log", "LOG");
+        let repo = git2::Repository::init(env.dir.path()).unwrap();
+        // Everything is force-added, so the ignore rules have something to filter out
+        let commit = commit_paths(&repo, &["lowercase", "uppercase"], "v1");
+        repo.tag_lightweight("v1", &repo.find_object(commit, None).unwrap(), false)
+            .unwrap();
+
+        // Change the working tree after the tag
+        create_temp_file(env.dir.path(), "lowercase/foo.py", "changed foo.py");
+        fs::remove_file(env.dir.path().join("lowercase/bar.py")).unwrap();
+        create_temp_file(env.dir.path(), "lowercase/new.py", "content new.py");
+
+        env.command().arg("--rev=v1").assert().success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("changed foo.py").not().eval(&output));
+        assert!(contains("content bar.py").eval(&output));
+        assert!(contains("new.py").not().eval(&output));
+        assert!(contains("content qux.txt").not().eval(&output));
+        assert!(contains("build.log").not().eval(&output));
+        assert!(contains("content baz.py").eval(&output));
+
+        env.command()
+            .arg("--rev=v1")
+            .arg("--include=**/*.py")
+            .arg("--exclude=**/baz.py")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("content baz.py").not().eval(&output));
+        assert!(contains("content corge.txt").not().eval(&output));
+
+        // A revision only holds tracked files anyway
+        env.command()
+            .arg("--rev=v1")
+            .arg("--tracked-only")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("changed foo.py").not().eval(&output));
+        assert!(contains("new.py").not().eval(&output));
+
+        env.command()
+            .arg("--rev=v9")
+            .assert()
+            .failure()
+            .stderr(contains("Failed to read revision v9"));
+    }
+
+    #[test]
+    fn test_tracked_only_selects_git_files() {
+        let env = TestEnv::new();
+        create_temp_file(env.dir.path(), "lowercase/build.log", "content build.log");
+        let repo = git2::Repository::init(env.dir.path()).unwrap();
+        commit_paths(&repo, &["lowercase/foo.py", "lowercase/build.log"], "v1");
+
+        // Tracked files are selected even if the default excludes would skip them
+        env.command().arg("--tracked-only").assert().success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("content build.log").eval(&output));
+        assert!(contains("content bar.py").not().eval(&output));
+        assert!(contains("uppercase").not().eval(&output));
+
+        env.command()
+            .arg("--tracked-only")
+            .arg("--untracked")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("content bar.py").eval(&output));
+        assert!(contains("CONTENT FOO.PY").eval(&output));
+
+        env.command()
+            .arg("--untracked")
+            .assert()
+            .failure()
+            .stderr(contains("--tracked-only"));
+    }
+
+    #[test]
+    fn test_git_errors_are_warnings() {
+        let env = TestEnv::new();
+        // A submodule's checkout only has a `.git` file pointing into the parent repository
+        create_temp_file(
+            env.dir.path(),
+            "lowercase/.git",
+            "gitdir: ../.git/modules/lowercase",
+        );
+
+        env.command()
+            .arg("--diff")
+            .arg("--git-diff-branch=main,feature")
+            .assert()
+            .success()
+            .stderr(contains(
+                "Failed to generate git diff: Failed to open repository",
+            ))
+            .stderr(contains("Failed to generate git diff between revisions"));
+        let output = env.read_output();
+        assert!(contains("content foo.py").eval(&output));
+        assert!(contains("gitdir:").not().eval(&output));
+
+        create_temp_file(env.dir.path(), "all.hbs", "ALL[{{git_diff_all}}]");
+        env.command()
+            .arg("--diff")
+            .arg(format!(
+                "--template={}",
+                env.dir.path().join("all.hbs").display()
+            ))
+            .assert()
+            .success()
+            .stderr(contains("Failed to generate git_diff_all"));
+    }
+
+    #[test]
+    fn test_templates_by_name() {
+        let env = TestEnv::new();
+        let user_dir = env.dir.path().join(".config/code2prompt/templates");
+        fs::create_dir_all(&user_dir).unwrap();
+        fs::write(
+            user_dir.join("just-paths.hbs"),
+            "{{!-- Only the paths --}}\n{{#each files}}PATH {{path}}\n{{/each}}",
+        )
+        .unwrap();
+
+        env.command()
+            .arg("--template=write-git-commit")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success();
+        assert!(contains("git commit message").eval(&env.read_output()));
+
+        env.command()
+            .arg("--template=just-paths")
+            .arg("--exclude=**/output.txt")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("PATH ").eval(&output));
+        assert!(contains("content foo.py").not().eval(&output));
+
+        Command::cargo_bin("code2prompt")
+            .unwrap()
+            .arg("--list-templates")
+            .env("XDG_CONFIG_HOME", env.dir.path().join(".config"))
+            .assert()
+            .success()
+            .stdout(contains("just-paths (user)"))
+            .stdout(contains("Only the paths"))
+            .stdout(contains("fix-bugs"));
+
+        env.command()
+            .arg("--template=no-such-template")
+            .assert()
+            .failure()
+            .stderr(contains("--list-templates"));
+    }
+
+    #[test]
+    fn test_template_variables_from_the_command_line() {
+        let env = TestEnv::new();
+        let template = env.dir.path().join("vars.hbs");
+        fs::write(
+            &template,
+            "Ticket: {{ticket}}\nNotes: {{notes}}\nLevel: {{level}}",
+        )
+        .unwrap();
+        let var_file = env.dir.path().join("vars.json");
+        fs::write(&var_file, r#"{"ticket": "BUG-1", "level": "high"}"#).unwrap();
+        create_temp_file(env.dir.path(), "notes.txt", "see the logs");
+
+        env.command()
+            .arg(format!("--template={}", template.display()))
+            .arg(format!("--var-file={}", var_file.display()))
+            .arg("--var=ticket=BUG-2")
+            .arg(format!(
+                "--var=notes=@{}",
+                env.dir.path().join("notes.txt").display()
+            ))
+            .arg("--no-interactive")
+            .assert()
+            .success();
+        let output = env.read_output();
+        assert!(contains("Ticket: BUG-2").eval(&output));
+        assert!(contains("Notes: see the logs").eval(&output));
+        assert!(contains("Level: high").eval(&output));
+
+        env.command()
+            .arg(format!("--template={}", template.display()))
+            .arg("--var=ticket=BUG-2")
+            .arg("--no-interactive")
+            .assert()
+            .failure()
+            .stderr(contains(
+                "Missing values for template variables: notes, level",
+            ));
+    }
 }
@@ -1,4 +1,8 @@
-use code2prompt::template::{extract_undefined_variables, handlebars_setup, render_template};
+use code2prompt::template::{
+    extract_undefined_variables, find_template, handle_undefined_variables, handlebars_setup,
+    list_templates, load_template_variables, render_template, Template, TemplateSource,
+    DEFAULT_TEMPLATE_NAME,
+};
 
 #[cfg(test)]
 mod tests {
@@ -36,6 +40,19 @@ mod tests {
         assert_eq!(variables, vec!["name", "language", "framework"]);
     }
 
+    #[test]
+    fn test_extract_undefined_variables_scopes_item_fields() {
+        let template_str = "Reviewer: {{author}} on {{ date }}\n\
+            {{#each commits}}{{short_sha}} {{author}} {{{body}}}\n\
+            {{#each files}}{{path}} +{{insertions}}{{/each}}{{/each}}\n\
+            {{#each files}}{{#if encoding}}{{encoding}}{{else}}{{code}}{{/if}}{{/each}}\n\
+            {{#each diff_files}}{{change_type}} {{old_path}}\n{{hunks}}{{/each}}\n\
+            {{#each diff_files_branch}}{{#unless binary}}+{{additions}}{{/unless}}{{/each}}\n\
+            {{summary}} {{hunks}}";
+        let variables = extract_undefined_variables(template_str);
+        assert_eq!(variables, vec!["author", "date", "summary", "hunks"]);
+    }
+
     #[test]
     fn test_render_template() {
         let template_str = "{{greeting}}, {{name}}!";
@@ -49,4 +66,122 @@ mod tests {
             Err(e) => panic!("Template rendering failed: {}", e),
         }
     }
+
+    #[test]
+    fn test_find_template() {
+        let user_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            user_dir.path().join("fix-bugs.hbs"),
+            "{{!-- My own bug fixer --}}\n{{absolute_code_path}}",
+        )
+        .unwrap();
+
+        let builtin = find_template("write-git-commit", Some(user_dir.path())).unwrap();
+        assert_eq!(builtin.source, TemplateSource::Builtin);
+        assert!(builtin.content.contains("git commit message"));
+        let default = find_template(DEFAULT_TEMPLATE_NAME, None).unwrap();
+        assert!(default.content.contains("{{#each files}}"));
+
+        // User templates override the built-in ones with the same name
+        let user = find_template("fix-bugs", Some(user_dir.path())).unwrap();
+        assert_eq!(
+            user.source,
+            TemplateSource::User(user_dir.path().join("fix-bugs.hbs"))
+        );
+        assert_eq!(user.description(), Some("My own bug fixer"));
+        let builtin = find_template("fix-bugs", None).unwrap();
+        assert_eq!(builtin.source, TemplateSource::Builtin);
+
+        // Anything else is a path
+        let path = user_dir.path().join("fix-bugs.hbs");
+        let file = find_template(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(file.name, "fix-bugs");
+        assert_eq!(file.source, TemplateSource::File(path));
+
+        assert!(find_template("no-such-template", Some(user_dir.path())).is_err());
+    }
+
+    #[test]
+    fn test_list_templates() {
+        let user_dir = tempfile::tempdir().unwrap();
+        std::fs::write(user_dir.path().join("mine.hbs"), "{{! Mine }}\n{{code}}").unwrap();
+        std::fs::write(user_dir.path().join("notes.txt"), "not a template").unwrap();
+
+        let templates = list_templates(Some(user_dir.path())).unwrap();
+        let names: Vec<_> = templates.iter().map(|t| t.name.as_str()).collect();
+        assert!(names.contains(&"default"));
+        assert!(names.contains(&"fix-bugs"));
+        assert!(names.contains(&"mine"));
+        assert!(!names.contains(&"notes"));
+        assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+        // Every bundled template describes itself
+        assert!(templates.iter().all(|t| t.description().is_some()));
+
+        let missing = user_dir.path().join("missing");
+        assert!(list_templates(Some(&missing)).unwrap().len() < templates.len());
+    }
+
+    #[test]
+    fn test_template_description() {
+        let template = |content: &str| Template {
+            name: "test".to_string(),
+            content: content.to_string(),
+            source: TemplateSource::Builtin,
+        };
+        assert_eq!(
+            template("{{!-- Fix the bugs. --}}\nbody").description(),
+            Some("Fix the bugs.")
+        );
+        assert_eq!(
+            template("{{! Short form }}").description(),
+            Some("Short form")
+        );
+        assert_eq!(template("{{!-- --}}\nbody").description(), None);
+        assert_eq!(template("Project Path: {{path}}").description(), None);
+    }
+
+    #[test]
+    fn test_load_template_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("vars.json");
+        std::fs::write(&json_file, r#"{"ticket": "BUG-1", "severity": "high"}"#).unwrap();
+        let toml_file = dir.path().join("vars.toml");
+        std::fs::write(&toml_file, "severity = \"low\"\nretries = 3\n").unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "line one\nline two\n").unwrap();
+
+        let vars = vec![
+            "ticket=BUG-2".to_string(),
+            "query=a=b".to_string(),
+            format!("notes=@{}", notes.display()),
+        ];
+        let variables = load_template_variables(&vars, &[json_file.clone(), toml_file]).unwrap();
+        // Later files override earlier ones, and --var overrides both
+        assert_eq!(variables["ticket"], json!("BUG-2"));
+        assert_eq!(variables["severity"], json!("low"));
+        assert_eq!(variables["retries"], json!(3));
+        assert_eq!(variables["query"], json!("a=b"));
+        assert_eq!(variables["notes"], json!("line one\nline two\n"));
+
+        assert!(load_template_variables(&["novalue".to_string()], &[]).is_err());
+        assert!(load_template_variables(&["=value".to_string()], &[]).is_err());
+        assert!(load_template_variables(&["notes=@missing.txt".to_string()], &[]).is_err());
+        assert!(load_template_variables(&[], &[notes]).is_err());
+        std::fs::write(&json_file, "[1, 2]").unwrap();
+        assert!(load_template_variables(&[], &[json_file]).is_err());
+    }
+
+    #[test]
+    fn test_handle_undefined_variables_without_prompting() {
+        let template = "{{ticket}} {{severity}} {{ticket}} {{absolute_code_path}}";
+        let mut data = json!({ "absolute_code_path": "repo", "ticket": "BUG-1" });
+        let err = handle_undefined_variables(&mut data, template, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing values for template variables: severity (set them with --var or --var-file)"
+        );
+
+        data["severity"] = json!("high");
+        handle_undefined_variables(&mut data, template, false).unwrap();
+    }
 }
@@ -1,3 +1,5 @@
+#![allow(deprecated)]
+
 use code2prompt::filter::should_include_file as deprecated_should_include_file;
 use colored::*;
 use once_cell::sync::Lazy;
@@ -9,10 +11,12 @@ use tempfile::{tempdir, TempDir};
 fn create_temp_file(dir: &Path, name: &str, content: &str) {
     let file_path = dir.join(name);
     let parent_dir = file_path.parent().unwrap();
-    fs::create_dir_all(parent_dir).expect(&format!("Failed to create directory: {:?}", parent_dir));
-    let mut file =
-        File::create(&file_path).expect(&format!("Failed to create temp file: {:?}", file_path));
-    writeln!(file, "{}", content).expect(&format!("Failed to write to temp file: {:?}", file_path));
+    fs::create_dir_all(parent_dir)
+        .unwrap_or_else(|_| panic!("Failed to create directory: {:?}", parent_dir));
+    let mut file = File::create(&file_path)
+        .unwrap_or_else(|_| panic!("Failed to create temp file: {:?}", file_path));
+    writeln!(file, "{}", content)
+        .unwrap_or_else(|_| panic!("Failed to write to temp file: {:?}", file_path));
 }
 
 static TEST_DIR: Lazy<TempDir> = Lazy::new(|| {
@@ -280,15 +284,13 @@ mod tests {
         let exclude_patterns = vec!["**/foo.py".to_string()];
         let include_priority = true;
 
-        for file in ["lowercase/foo.py"] {
-            let path = base_path.join(file);
-            assert!(should_include_file(
-                &path,
-                &include_patterns,
-                &exclude_patterns,
-                include_priority
-            ));
-        }
+        let path = base_path.join("lowercase/foo.py");
+        assert!(should_include_file(
+            &path,
+            &include_patterns,
+            &exclude_patterns,
+            include_priority
+        ));
 
         for file in [
             "lowercase/bar.py",
@@ -363,7 +365,7 @@ mod tests {
         let exclude_patterns: Vec<String> = vec![];
         let include_priority = false;
         assert!(should_include_file(
-            &path,
+            path,
             &include_patterns,
             &exclude_patterns,
             include_priority
@@ -377,7 +379,7 @@ mod tests {
         let exclude_patterns: Vec<String> = vec!["*.rs".to_string()];
         let include_priority = false;
         assert!(!should_include_file(
-            &path,
+            path,
             &include_patterns,
             &exclude_patterns,
             include_priority
@@ -0,0 +1,153 @@
+use code2prompt::token::{
+    fit_to_token_budget, get_model, get_model_info, get_tokenizer, split_into_parts,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_get_tokenizer_supports_o200k() {
+        let tokenizer =
+            get_tokenizer(&Some("o200k".to_string())).expect("o200k should be supported");
+        assert!(tokenizer.count("Hello, world!").unwrap() > 0);
+
+        // `_base` aliases resolve to the same encoding
+        assert!(get_tokenizer(&Some("o200k_base".to_string())).is_ok());
+    }
+
+    #[test]
+    fn test_get_tokenizer_rejects_unknown_encoding() {
+        let Err(err) = get_tokenizer(&Some("not-an-encoding".to_string())) else {
+            panic!("Unknown encodings should be an error");
+        };
+        assert!(err.to_string().contains("not-an-encoding"));
+    }
+
+    #[test]
+    fn test_get_tokenizer_loads_tokenizer_json() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("tokenizer.json");
+        fs::write(
+            &path,
+            r#"{
+                "version": "1.0",
+                "truncation": null,
+                "padding": null,
+                "added_tokens": [],
+                "normalizer": null,
+                "pre_tokenizer": { "type": "Whitespace" },
+                "post_processor": null,
+                "decoder": null,
+                "model": {
+                    "type": "WordLevel",
+                    "vocab": { "hello": 0, "world": 1, "[UNK]": 2 },
+                    "unk_token": "[UNK]"
+                }
+            }"#,
+        )
+        .expect("Failed to write tokenizer.json");
+
+        let encoding = Some(path.to_str().unwrap().to_string());
+        let tokenizer = get_tokenizer(&encoding).expect("Failed to load tokenizer.json");
+        assert_eq!(tokenizer.count("hello world again").unwrap(), 3);
+        assert!(get_model_info(&encoding, None, 3).starts_with("Custom tokenizer"));
+    }
+
+    #[test]
+    fn test_get_tokenizer_loads_vocab_merges_pair() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let vocab = temp_dir.path().join("vocab.json");
+        let merges = temp_dir.path().join("merges.txt");
+        fs::write(&vocab, r#"{"Ġ": 0, "a": 1, "b": 2, "ab": 3, "Ġab": 4}"#)
+            .expect("Failed to write vocab.json");
+        fs::write(&merges, "#version: 0.2\na b\nĠ ab\n").expect("Failed to write merges.txt");
+
+        let encoding = Some(format!("{},{}", vocab.display(), merges.display()));
+        let tokenizer = get_tokenizer(&encoding).expect("Failed to load vocab/merges pair");
+        assert_eq!(tokenizer.count("ab ab").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_get_model_matches_longest_prefix() {
+        let model = get_model("gpt-4o-mini").expect("gpt-4o-mini should be known");
+        assert_eq!(model.encoding, "o200k");
+        assert_eq!(model.context_window, 128_000);
+
+        let model = get_model("gpt-4-32k-0613").expect("gpt-4-32k should be known");
+        assert_eq!(model.encoding, "cl100k");
+        assert_eq!(model.context_window, 32_768);
+
+        let model = get_model("claude-3-5-sonnet").expect("claude should be known");
+        assert_eq!(model.context_window, 200_000);
+
+        assert!(get_model("mystery-model").is_err());
+    }
+
+    #[test]
+    fn test_get_model_info_reports_context_usage() {
+        let model = get_model("gpt-4o").unwrap();
+        let info = get_model_info(&None, Some(&model), 64_000);
+        assert_eq!(info, "gpt-4o: 64000 of 128000 tokens used (50.0%)");
+
+        let info = get_model_info(&None, None, 64_000);
+        assert_eq!(info, "ChatGPT models, text-embedding-ada-002");
+    }
+
+    #[test]
+    fn test_fit_to_token_budget_cuts_largest_file_first() {
+        let tokenizer = get_tokenizer(&None).unwrap();
+        let mut folders = vec![json!({
+            "files": [
+                { "path": "small.rs", "code": "fn small() {}" },
+                { "path": "large.rs", "code": "let x = 1;\n".repeat(500) },
+            ]
+        })];
+        let render = |folders: &[serde_json::Value]| -> anyhow::Result<String> {
+            Ok(folders[0]["files"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|file| file["code"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"))
+        };
+
+        let (output, cuts) = fit_to_token_budget(&mut folders, 100, &tokenizer, render).unwrap();
+
+        assert!(tokenizer.count(&output).unwrap() <= 100);
+        assert!(output.contains("fn small() {}"));
+        assert_eq!(cuts.len(), 1);
+        assert_eq!(cuts[0].path, "large.rs");
+        assert!(cuts[0].kept_tokens < cuts[0].original_tokens);
+    }
+
+    #[test]
+    fn test_split_into_parts_splits_long_lines() {
+        let tokenizer = get_tokenizer(&None).unwrap();
+        let folders = vec![json!({
+            "files": [{ "path": "minified.js", "code": "word ".repeat(1000) }]
+        })];
+        let render = |folders: &[serde_json::Value]| -> anyhow::Result<String> {
+            Ok(folders[0]["files"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|file| file["code"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"))
+        };
+
+        let parts = split_into_parts(&folders, 200, &tokenizer, render).unwrap();
+
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(tokenizer.count(part).unwrap() <= 200);
+        }
+        let words: usize = parts.iter().map(|part| part.matches('w').count()).sum();
+        assert_eq!(words, 1000);
+    }
+}